@@ -18,6 +18,15 @@ pub trait CmdHandler {
     fn unattach(&mut self, request: &mut Request) -> result::Result<String, CmdError>;
     fn log(&self, request: &mut Request) -> result::Result<String, CmdError>;
     fn head(&self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn read(&self, request: &mut Request) -> result::Result<String, CmdError>;
     fn status(&self, request: &mut Request) -> result::Result<String, CmdError>;
-    fn other(&self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn input(&mut self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn winsize(&self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn interrupt(&self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn subscribe(&self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn signal(&self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn scale(&mut self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn reload(&mut self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn restart(&mut self, request: &mut Request) -> result::Result<String, CmdError>;
+    fn other(&mut self, request: &mut Request) -> result::Result<String, CmdError>;
 }
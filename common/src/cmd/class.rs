@@ -5,8 +5,19 @@ pub enum Cmd {
     Log,
     Status,
     Head,
+    Read,
     Attach,
     Unattach,
+    Input,
+    Winsize,
+    Interrupt,
+    Cancel,
+    Subscribe,
+    Signal,
+    Scale,
+    Shutdown,
+    Reload,
+    Restart,
     Other(String),
 }
 
@@ -16,11 +27,28 @@ impl Cmd {
             "LOG" => Ok(Cmd::Log),
             "STATUS" => Ok(Cmd::Status),
             "HEAD" => Ok(Cmd::Head),
+            "READ" => Ok(Cmd::Read),
             "ATTACH" => Ok(Cmd::Attach),
             "UNATTACH" => Ok(Cmd::Unattach),
+            "INPUT" => Ok(Cmd::Input),
+            "WINSIZE" => Ok(Cmd::Winsize),
+            "INTERRUPT" => Ok(Cmd::Interrupt),
+            "CANCEL" => Ok(Cmd::Cancel),
+            "SUBSCRIBE" => Ok(Cmd::Subscribe),
+            "SIGNAL" => Ok(Cmd::Signal),
+            "SCALE" => Ok(Cmd::Scale),
+            "SHUTDOWN" => Ok(Cmd::Shutdown),
+            "RELOAD" => Ok(Cmd::Reload),
+            "RESTART" => Ok(Cmd::Restart),
             other => Ok(Cmd::Other(other.to_string())),
         }
     }
+
+    /// Commands that keep their meaning while a client is attached, everything
+    /// else is input for the attached program.
+    pub fn is_control(&self) -> bool {
+        matches!(self, Cmd::Unattach | Cmd::Input | Cmd::Winsize | Cmd::Interrupt)
+    }
 }
 
 impl fmt::Display for Cmd {
@@ -36,8 +64,19 @@ impl From<Cmd> for String {
             Cmd::Log => "LOG".to_string(),
             Cmd::Status => "STATUS".to_string(),
             Cmd::Head => "HEAD".to_string(),
+            Cmd::Read => "READ".to_string(),
             Cmd::Attach => "ATTACH".to_string(),
             Cmd::Unattach => "UNATTACH".to_string(),
+            Cmd::Input => "INPUT".to_string(),
+            Cmd::Winsize => "WINSIZE".to_string(),
+            Cmd::Interrupt => "INTERRUPT".to_string(),
+            Cmd::Cancel => "CANCEL".to_string(),
+            Cmd::Subscribe => "SUBSCRIBE".to_string(),
+            Cmd::Signal => "SIGNAL".to_string(),
+            Cmd::Scale => "SCALE".to_string(),
+            Cmd::Shutdown => "SHUTDOWN".to_string(),
+            Cmd::Reload => "RELOAD".to_string(),
+            Cmd::Restart => "RESTART".to_string(),
             Cmd::Other(cmd) => cmd.to_string(),
         }
     }
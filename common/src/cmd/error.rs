@@ -1,8 +1,11 @@
 use std::fmt;
 
+use super::CmdErrorKind;
+
 #[derive(Debug)]
 pub struct CmdError {
     pub message: String,
+    pub kind:    Option<CmdErrorKind>,
 }
 
 impl std::error::Error for CmdError {}
@@ -17,22 +20,32 @@ impl CmdError {
     pub fn new(message: &str) -> CmdError {
         CmdError {
             message: message.into(),
+            kind:    None,
         }
     }
-}
 
-impl From<&str> for CmdError {
-    fn from(message: &str) -> Self {
+    /// Error of a known kind, its description following the context.
+    pub fn with_kind(context: &str, kind: CmdErrorKind) -> CmdError {
         CmdError {
-            message: message.into(),
+            message: format!("{context}: {kind}"),
+            kind:    Some(kind),
         }
     }
 }
 
+impl From<&str> for CmdError {
+    fn from(message: &str) -> Self { CmdError::new(message) }
+}
+
 impl From<CmdError> for String {
     fn from(value: CmdError) -> Self { value.message }
 }
 
 impl From<String> for CmdError {
-    fn from(message: String) -> Self { CmdError { message } }
+    fn from(message: String) -> Self {
+        CmdError {
+            message,
+            kind: None,
+        }
+    }
 }
@@ -1,8 +1,10 @@
 use core::fmt;
 
+use serde::{Deserialize, Serialize};
+
 use super::CmdError;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CmdErrorKind {
     NotFound(String),
     InvalidArguments,
@@ -30,7 +32,8 @@ impl From<CmdErrorKind> for String {
 impl From<CmdErrorKind> for CmdError {
     fn from(value: CmdErrorKind) -> Self {
         CmdError {
-            message: value.into(),
+            message: value.clone().into(),
+            kind:    Some(value),
         }
     }
 }
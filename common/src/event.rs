@@ -0,0 +1,90 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Something a subscribed client is told about.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    Process {
+        program:  String,
+        instance: usize,
+        pid:      Option<u32>,
+        #[serde(flatten)]
+        state:    ProcessState,
+        /// Seconds since the epoch
+        at:       u64,
+    },
+    Reload {
+        config: String,
+    },
+    Shutdown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum ProcessState {
+    Starting,
+    Active,
+    Exited {
+        code:    u32,
+        success: bool,
+    },
+    Killed {
+        signal: String,
+    },
+    /// Died too early, started again after `retry` attempts
+    Backoff {
+        retry: u32,
+    },
+    /// Gave up starting it
+    Fatal,
+    /// Asked to exit by the daemon
+    Stopping,
+    Stopped,
+}
+
+impl fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessState::Starting => write!(f, "starting"),
+            ProcessState::Active => write!(f, "active"),
+            ProcessState::Exited { code, .. } => write!(f, "exited {code}"),
+            ProcessState::Killed { signal } => write!(f, "killed {signal}"),
+            ProcessState::Backoff { retry } => write!(f, "backoff {retry}"),
+            ProcessState::Fatal => write!(f, "fatal"),
+            ProcessState::Stopping => write!(f, "stopping"),
+            ProcessState::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Process {
+                program,
+                instance,
+                pid,
+                state,
+                ..
+            } => match pid {
+                Some(pid) => write!(f, "{program}[{instance}] {state} (pid {pid})"),
+                None => write!(f, "{program}[{instance}] {state}"),
+            },
+            Event::Reload { config } => write!(f, "reload {config}"),
+            Event::Shutdown => write!(f, "shutdown"),
+        }
+    }
+}
+
+impl Event {
+    /// One line per event, JSON for clients that talk JSON.
+    pub fn encode(&self, json: bool) -> String {
+        match json {
+            // events hold nothing serde_json can refuse
+            true => serde_json::to_string(self).unwrap() + "\n",
+            false => format!("{self}\n"),
+        }
+    }
+}
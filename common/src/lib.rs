@@ -1,12 +1,18 @@
 #![feature(raw_os_error_ty)]
 
 mod cmd;
+mod encoding;
+mod event;
 mod macros;
+pub mod protocol;
 pub mod request;
 pub mod response;
 pub mod server;
 
 pub use cmd::{Cmd, CmdError, CmdErrorKind, CmdHandler};
+pub use encoding::{hex_decode, hex_encode};
+pub use event::{Event, ProcessState};
+pub use protocol::{Encoding, PROTOCOL_VERSION};
 pub use request::{ClientState, Request, RequestError, RequestStatus};
 pub use response::Response;
 pub use server::Key;
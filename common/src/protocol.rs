@@ -0,0 +1,183 @@
+//! Wire format spoken between ctl and the daemon.
+//!
+//! Messages are framed by a 4-byte big-endian length header, so they may
+//! hold any byte. A peer whose first byte is not 0 cannot be sending a header
+//! and is read line by line instead, each '\n'-terminated line a message.
+//! A message starting with `{` is a JSON frame, any other is a plain text
+//! command answered with a bare string, kept for clients that predate the
+//! JSON protocol.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ClientState, CmdErrorKind, RequestError};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How a client talks, responses are encoded the way the request was.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Encoding {
+    pub fn of(line: &str) -> Encoding {
+        match line.trim_start().starts_with('{') {
+            true => Encoding::Json,
+            false => Encoding::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Argument {
+    Number(i64),
+    Flag(bool),
+    Text(String),
+}
+
+impl fmt::Display for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Argument::Number(n) => write!(f, "{n}"),
+            Argument::Flag(b) => write!(f, "{b}"),
+            Argument::Text(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+impl From<&str> for Argument {
+    fn from(value: &str) -> Self {
+        // only when read back the same, "+1" or "007" say more than a number
+        match value.parse::<i64>() {
+            Ok(n) if n.to_string() == value => Argument::Number(n),
+            _ => Argument::Text(value.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestFrame {
+    pub version:   u32,
+    #[serde(default)]
+    pub id:        u64,
+    pub command:   String,
+    #[serde(default)]
+    pub arguments: Vec<Argument>,
+}
+
+impl RequestFrame {
+    pub fn new(id: u64, command: &str, arguments: Vec<Argument>) -> RequestFrame {
+        RequestFrame {
+            version: PROTOCOL_VERSION,
+            id,
+            command: command.into(),
+            arguments,
+        }
+    }
+
+    /// Frame for a command typed as text, `None` for a blank line.
+    pub fn from_text(id: u64, line: &str) -> Option<RequestFrame> {
+        let mut words = line.split_whitespace();
+        let command = words.next()?;
+        Some(RequestFrame::new(id, command, words.map(Argument::from).collect()))
+    }
+
+    pub fn decode(line: &str) -> Result<RequestFrame, RequestError> {
+        let frame: RequestFrame = serde_json::from_str(line)
+            .map_err(|e| RequestError::from(format!("malformed request: {e}")))?;
+        check_version(frame.version)?;
+        Ok(frame)
+    }
+
+    pub fn encode(&self) -> String { encode_line(self) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseStatus {
+    Ok,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResponseFrame {
+    pub version:  u32,
+    pub id:       u64,
+    pub status:   ResponseStatus,
+    pub payload:  String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error:    Option<CmdErrorKind>,
+    pub finished: bool,
+    /// Session of the client once the request was handled
+    pub state:    ClientState,
+    /// Whether the client is attached to a program running on a terminal
+    #[serde(default)]
+    pub terminal: bool,
+}
+
+impl ResponseFrame {
+    pub fn decode(line: &str) -> Result<ResponseFrame, RequestError> {
+        let frame: ResponseFrame = serde_json::from_str(line)
+            .map_err(|e| RequestError::from(format!("malformed response: {e}")))?;
+        check_version(frame.version)?;
+        Ok(frame)
+    }
+
+    pub fn encode(&self) -> String { encode_line(self) }
+}
+
+fn check_version(version: u32) -> Result<(), RequestError> {
+    match version {
+        PROTOCOL_VERSION => Ok(()),
+        other => Err(format!("unsupported protocol version {other}").into()),
+    }
+}
+
+fn encode_line<T: Serialize>(frame: &T) -> String {
+    // frames hold nothing serde_json can refuse
+    let mut line = serde_json::to_string(frame).unwrap();
+    line.push('\n');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_commands_become_typed_frames() {
+        let frame = RequestFrame::from_text(7, "log sleep -n 20\n").unwrap();
+
+        assert_eq!(frame.command, "log");
+        assert_eq!(
+            frame.arguments,
+            [
+                Argument::Text("sleep".into()),
+                Argument::Text("-n".into()),
+                Argument::Number(20)
+            ]
+        );
+        assert_eq!(RequestFrame::decode(&frame.encode()).unwrap(), frame);
+        assert!(RequestFrame::from_text(8, "  \n").is_none());
+        assert_eq!(Argument::from("+1"), Argument::Text("+1".into()));
+    }
+
+    #[test]
+    fn frames_of_another_version_are_refused() {
+        let line = r#"{"version":2,"id":1,"command":"status"}"#;
+
+        assert!(RequestFrame::decode(line).is_err());
+        assert!(RequestFrame::decode("{not json").is_err());
+    }
+
+    #[test]
+    fn encoding_is_told_by_the_first_character() {
+        assert_eq!(Encoding::of(r#" {"version":1}"#), Encoding::Json);
+        assert_eq!(Encoding::of("status"), Encoding::Text);
+    }
+}
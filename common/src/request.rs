@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 mod class;
 mod error;
 mod status;
@@ -6,7 +8,7 @@ pub use class::Request;
 pub use error::{RequestError, Result};
 pub use status::RequestStatus;
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ClientState {
     #[default]
     Unattached,
@@ -1,5 +1,6 @@
+use crate::protocol::RequestFrame;
 use crate::server::Key;
-use crate::{ClientState, Cmd, CmdErrorKind, RequestStatus};
+use crate::{ClientState, Cmd, CmdErrorKind, Encoding, RequestError, RequestStatus};
 
 #[derive(Debug, Clone)]
 pub struct Request {
@@ -10,25 +11,48 @@ pub struct Request {
     pub state:      ClientState,
     pub error:      Option<CmdErrorKind>,
     pub client_key: Key,
+    pub id:         u64,
+    pub encoding:   Encoding,
+    pub raw:        String,
+    /// How far a streaming request got, kept by its handler between responses
+    pub cursor:     Option<u64>,
 }
 
-impl From<&String> for Request {
-    fn from(s: &String) -> Self { Request::from(s.as_str()) }
-}
+impl Request {
+    pub fn new() -> Request { Self::default() }
 
-impl From<&str> for Request {
-    fn from(value: &str) -> Self {
-        let v: Vec<&str> = value.split_whitespace().collect();
-        Request {
-            command: Cmd::parse(v.first().unwrap()).unwrap(),
-            arguments: v[1..].iter().map(|x| x.to_string()).collect(),
+    /// Reads a request line, either a JSON frame or a plain text command.
+    pub fn parse(line: &str) -> Result<Request, RequestError> {
+        let encoding = Encoding::of(line);
+        let frame = match encoding {
+            Encoding::Json => RequestFrame::decode(line)?,
+            Encoding::Text => match RequestFrame::from_text(0, line) {
+                Some(frame) => frame,
+                None => return Err("empty request".into()),
+            },
+        };
+        let arguments: Vec<String> =
+            frame.arguments.iter().map(ToString::to_string).collect();
+        // what an attached program receives when the command is meant for it
+        let raw = match encoding {
+            Encoding::Json => std::iter::once(&frame.command)
+                .chain(&arguments)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" "),
+            Encoding::Text => line.into(),
+        };
+        Ok(Request {
+            command: Cmd::parse(&frame.command).map_err(|e| e.message)?,
+            arguments,
+            id: frame.id,
+            encoding,
+            raw,
             ..Default::default()
-        }
+        })
     }
-}
 
-impl Request {
-    pub fn new() -> Request { Self::default() }
+    pub fn is_streaming(&self) -> bool { !self.finished && self.cursor.is_some() }
 
     pub fn is_valid(&mut self) -> bool {
         match self.status {
@@ -66,7 +90,11 @@ impl Default for Request {
             finished:   false,
             state:      ClientState::default(),
             client_key: Key::default(),
+            id:         0,
+            encoding:   Encoding::default(),
             error:      None,
+            raw:        String::new(),
+            cursor:     None,
         }
     }
 }
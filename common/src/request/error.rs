@@ -12,4 +12,16 @@ impl fmt::Display for RequestError {
     }
 }
 
+impl From<String> for RequestError {
+    fn from(message: String) -> Self { RequestError { message } }
+}
+
+impl From<&str> for RequestError {
+    fn from(message: &str) -> Self {
+        RequestError {
+            message: message.into(),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, RequestError>;
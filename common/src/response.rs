@@ -1,8 +1,70 @@
-use crate::Key;
+use crate::protocol::{ResponseFrame, ResponseStatus};
+use crate::{
+    ClientState,
+    CmdError,
+    CmdErrorKind,
+    Encoding,
+    Key,
+    Request,
+    PROTOCOL_VERSION,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Response {
     pub message:    String,
     pub finished:   bool,
     pub client_key: Key,
+    pub id:         u64,
+    pub encoding:   Encoding,
+    pub failed:     bool,
+    pub error:      Option<CmdErrorKind>,
+    pub state:      ClientState,
+    pub terminal:   bool,
+}
+
+impl Response {
+    /// Answer to a request, in the state the request left the client in.
+    pub fn new(request: &Request, result: Result<String, CmdError>) -> Response {
+        let (message, failed, error) = match result {
+            Ok(message) => (message, false, None),
+            Err(e) => (e.message, true, e.kind),
+        };
+        Response {
+            message,
+            finished: request.finished,
+            client_key: request.client_key,
+            id: request.id,
+            encoding: request.encoding,
+            failed,
+            error,
+            state: request.state.clone(),
+            terminal: false,
+        }
+    }
+
+    /// What is written to the client, in the encoding of its request.
+    pub fn encode(&self) -> String {
+        match self.encoding {
+            Encoding::Text => self.message.clone(),
+            Encoding::Json => ResponseFrame::from(self).encode(),
+        }
+    }
+}
+
+impl From<&Response> for ResponseFrame {
+    fn from(response: &Response) -> Self {
+        ResponseFrame {
+            version:  PROTOCOL_VERSION,
+            id:       response.id,
+            status:   match response.failed {
+                true => ResponseStatus::Error,
+                false => ResponseStatus::Ok,
+            },
+            payload:  response.message.clone(),
+            error:    response.error.clone(),
+            finished: response.finished,
+            state:    response.state.clone(),
+            terminal: response.terminal,
+        }
+    }
 }
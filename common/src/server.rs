@@ -1,7 +1,9 @@
 mod class;
+mod connection;
 mod error;
 
 pub use class::Server;
+pub use connection::{Connection, Framing, MAX_FRAME_LENGTH};
 pub use error::{Result, ServerError};
 
 pub type Key = u64;
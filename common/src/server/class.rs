@@ -1,9 +1,8 @@
 use std::collections::HashMap;
 use std::io;
-use std::io::{Read, Write};
 use std::os::fd::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use libc::{
     epoll_event,
@@ -16,15 +15,16 @@ use libc::{
 };
 use logger::{debug, error, info, warning};
 
-use super::{Key, SERVER_KEY, STDIN_KEY};
+use super::{Connection, Framing, Key, SERVER_KEY, STDIN_KEY};
 use crate::syscall;
 
 pub struct Server {
     pub socket:  UnixListener,
     pub events:  Vec<epoll_event>,
     pub pollfd:  RawFd,
-    pub clients: HashMap<u64, UnixStream>,
+    pub clients: HashMap<u64, Connection>,
     pub key:     u64,
+    path:        PathBuf,
     ready:       bool,
 }
 
@@ -50,6 +50,7 @@ impl Server {
             pollfd: RawFd::default(),
             clients: HashMap::new(),
             key: SERVER_KEY,
+            path: socket_path.into(),
             ready: false,
         }
     }
@@ -70,7 +71,7 @@ impl Server {
             Ok((stream, _addr)) => {
                 self.key += 1;
                 stream.set_nonblocking(true)?;
-                self.clients.insert(self.key, stream);
+                self.add_client(self.key, stream, Framing::Detect);
                 self.add_interest(Self::read_event(self.key))?;
                 Ok(self.key)
             }
@@ -115,17 +116,29 @@ impl Server {
         let mut fd: i32 = 0;
         let key = event.u64 as Key;
         if key != STDIN_KEY {
-            fd = self.clients.get(&key).unwrap().as_raw_fd();
+            fd = self.clients.get(&key).unwrap().stream.as_raw_fd();
         }
         syscall!(epoll_ctl(self.pollfd, EPOLL_CTL_ADD, fd, &mut event))?;
         Ok(())
     }
 
+    /// Polls another descriptor for input along with the clients, its events
+    /// come with `key`.
+    pub fn add_source(&self, key: Key, fd: RawFd) -> super::Result<()> {
+        syscall!(epoll_ctl(
+            self.pollfd,
+            EPOLL_CTL_ADD,
+            fd,
+            &mut Self::fixed_read(key)
+        ))?;
+        Ok(())
+    }
+
     pub fn modify_interest(&self, mut event: epoll_event) -> super::Result<()> {
         let mut fd: i32 = 0;
         let key = event.u64 as Key;
         if key != STDIN_KEY {
-            fd = self.clients.get(&key).unwrap().as_raw_fd();
+            fd = self.clients.get(&key).unwrap().stream.as_raw_fd();
         }
         syscall!(epoll_ctl(self.pollfd, EPOLL_CTL_MOD, fd, &mut event))?;
         Ok(())
@@ -134,7 +147,7 @@ impl Server {
     pub fn remove_interest(&self, key: Key) -> super::Result<()> {
         let mut fd: i32 = 0;
         if key != STDIN_KEY {
-            fd = self.clients.get(&key).unwrap().as_raw_fd();
+            fd = self.clients.get(&key).unwrap().stream.as_raw_fd();
         }
         syscall!(epoll_ctl(self.pollfd, EPOLL_CTL_DEL, fd, std::ptr::null_mut()))?;
         Ok(())
@@ -182,50 +195,75 @@ impl Server {
         }
     }
 
-    fn recv_stdin(&self) -> super::Result<String> {
+    fn recv_stdin(&self) -> super::Result<Vec<Vec<u8>>> {
         let mut buf = String::new();
         std::io::stdin().read_line(&mut buf)?;
         let stdin = STDIN_KEY;
         debug!("{:13}: |{}|", "STDIN", buf.escape_default());
         self.modify_interest(Server::read_event(stdin))?;
-        Ok(buf)
+        Ok(vec![buf.into_bytes()])
     }
 
-    pub fn recv(&mut self, key: Key) -> super::Result<String> {
+    /// Complete messages received from a client, none if it only sent part of
+    /// one so far.
+    pub fn recv(&mut self, key: Key) -> super::Result<Vec<Vec<u8>>> {
         if key == STDIN_KEY {
             return self.recv_stdin();
         }
 
-        let mut buf = [0_u8; 1024];
-        if let Some(client) = self.clients.get_mut(&key) {
-            match client.read(&mut buf) {
-                Ok(bytes) => {
-                    if bytes == 0 {
-                        warning!("#{key} DROPPED BY CLIENT (READ 0 BYTES)");
-                        return Err(io::Error::from_raw_os_error(32).into());
-                    }
-                    let res = String::from_utf8(buf[0..bytes].into()).unwrap();
-                    debug!("#{key} RECEIVED: |{}|", res.escape_default());
-                    Ok(res)
-                }
-                Err(e) => {
-                    self.remove_interest(key)?;
-                    self.clients.remove(&key);
-                    warning!("{key} removed from server due to: {e}");
-                    Err(e.into())
-                }
-            }
-        } else {
+        let Some(client) = self.clients.get_mut(&key) else {
             panic!("server: invalid key {key}");
+        };
+        match client.fill().and_then(|bytes| {
+            debug!("#{key} RECEIVED {bytes} BYTES");
+            client.messages()
+        }) {
+            Ok(messages) => Ok(messages),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                warning!("#{key} DROPPED BY CLIENT (READ 0 BYTES)");
+                Err(e.into())
+            }
+            Err(e) => {
+                self.remove_interest(key)?;
+                self.clients.remove(&key);
+                warning!("{key} removed from server due to: {e}");
+                Err(e.into())
+            }
         }
     }
 
-    pub fn send(&mut self, key: Key, msg: &String) -> super::Result<()> {
-        if let Some(client) = self.clients.get_mut(&key) {
-            client.write_all(msg.as_bytes())?;
-        } else {
-            error!("server: invalid key {key}");
+    /// Queues a message and writes what the client takes right away, true
+    /// once nothing is left to write.
+    pub fn send(&mut self, key: Key, msg: &[u8]) -> super::Result<bool> {
+        match self.clients.get_mut(&key) {
+            Some(client) => {
+                client.queue(msg)?;
+                Ok(client.flush()?)
+            }
+            None => {
+                error!("server: invalid key {key}");
+                Ok(true)
+            }
+        }
+    }
+
+    /// Writes the output still queued for a client, true once it is all sent.
+    pub fn flush(&mut self, key: Key) -> super::Result<bool> {
+        match self.clients.get_mut(&key) {
+            Some(client) => Ok(client.flush()?),
+            None => Ok(true),
+        }
+    }
+
+    pub fn add_client(&mut self, key: Key, stream: UnixStream, framing: Framing) {
+        self.clients.insert(key, Connection::new(stream, framing));
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warning!("{}: {e}", self.path.display());
         }
-        Ok(())
     }
 }
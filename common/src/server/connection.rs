@@ -0,0 +1,230 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+
+/// Largest message a connection accepts or sends, framing included.
+pub const MAX_FRAME_LENGTH: usize = 1024 * 1024;
+
+const HEADER_LENGTH: usize = 4;
+
+/// How messages are delimited on a connection.
+///
+/// Frames are prefixed with their length as a 4-byte big-endian integer, so
+/// the first byte of a framed stream is always 0 and a peer that starts with
+/// anything else is talking in '\n'-terminated lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Framing {
+    #[default]
+    Detect,
+    Lines,
+    Frames,
+}
+
+#[derive(Debug)]
+pub struct Connection {
+    pub stream:  UnixStream,
+    pub framing: Framing,
+    inbox:       Vec<u8>,
+    outbox:      Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(stream: UnixStream, framing: Framing) -> Connection {
+        Connection {
+            stream,
+            framing,
+            inbox: Vec::new(),
+            outbox: Vec::new(),
+        }
+    }
+
+    /// Reads everything available, fails once the peer hung up.
+    pub fn fill(&mut self) -> io::Result<usize> {
+        let mut buf = [0_u8; 4096];
+        let mut total = 0;
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) if total == 0 => return Err(ErrorKind::BrokenPipe.into()),
+                Ok(0) => return Ok(total),
+                Ok(bytes) => {
+                    self.inbox.extend_from_slice(&buf[..bytes]);
+                    total += bytes;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(total),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Takes the complete messages out of what was read so far.
+    pub fn messages(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        if self.framing == Framing::Detect {
+            self.framing = match self.inbox.first() {
+                None => return Ok(vec![]),
+                Some(0) => Framing::Frames,
+                Some(_) => Framing::Lines,
+            };
+        }
+        let mut messages = vec![];
+        while let Some(message) = self.next_message()? {
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    fn next_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self.framing {
+            Framing::Detect => Ok(None),
+            Framing::Lines => match self.inbox.iter().position(|&b| b == b'\n') {
+                Some(end) => Ok(Some(self.inbox.drain(..=end).collect())),
+                None if self.inbox.len() > MAX_FRAME_LENGTH => {
+                    Err(too_long(self.inbox.len()))
+                }
+                None => Ok(None),
+            },
+            Framing::Frames => {
+                let Some(header) = self.inbox.first_chunk::<HEADER_LENGTH>() else {
+                    return Ok(None);
+                };
+                let length = u32::from_be_bytes(*header) as usize;
+                if length + HEADER_LENGTH > MAX_FRAME_LENGTH {
+                    return Err(too_long(length));
+                }
+                if self.inbox.len() < HEADER_LENGTH + length {
+                    return Ok(None);
+                }
+                let frame = self.inbox.drain(..HEADER_LENGTH + length);
+                Ok(Some(frame.skip(HEADER_LENGTH).collect()))
+            }
+        }
+    }
+
+    /// Queues a message, framed the way the peer talks.
+    pub fn queue(&mut self, message: &[u8]) -> io::Result<()> {
+        if self.framing != Framing::Frames {
+            self.outbox.extend_from_slice(message);
+            return Ok(());
+        }
+        if message.len() + HEADER_LENGTH > MAX_FRAME_LENGTH {
+            return Err(too_long(message.len()));
+        }
+        self.outbox
+            .extend_from_slice(&(message.len() as u32).to_be_bytes());
+        self.outbox.extend_from_slice(message);
+        Ok(())
+    }
+
+    /// Writes as much of the queued output as the peer takes, true once
+    /// everything is sent.
+    pub fn flush(&mut self) -> io::Result<bool> {
+        while !self.outbox.is_empty() {
+            match self.stream.write(&self.outbox) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(bytes) => {
+                    self.outbox.drain(..bytes);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn has_pending_output(&self) -> bool { !self.outbox.is_empty() }
+}
+
+fn too_long(length: usize) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("message of {length} bytes exceeds {MAX_FRAME_LENGTH} bytes"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(framing: Framing) -> (Connection, Connection) {
+        let (a, b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        b.set_nonblocking(true).unwrap();
+        (Connection::new(a, framing), Connection::new(b, Framing::Detect))
+    }
+
+    #[test]
+    fn frames_carry_arbitrary_bytes() {
+        let (mut sender, mut receiver) = pair(Framing::Frames);
+        let binary = [0xff, 0x00, b'\n', 0xc3];
+
+        sender.queue(&binary).unwrap();
+        sender.queue(b"").unwrap();
+        assert!(sender.flush().unwrap());
+        receiver.fill().unwrap();
+
+        assert_eq!(receiver.messages().unwrap(), [binary.to_vec(), vec![]]);
+        assert_eq!(receiver.framing, Framing::Frames);
+    }
+
+    #[test]
+    fn partial_frames_wait_for_the_rest() {
+        let (mut sender, mut receiver) = pair(Framing::Frames);
+        let mut raw = sender.stream.try_clone().unwrap();
+
+        raw.write_all(&[0, 0, 0, 5, b'h', b'e']).unwrap();
+        receiver.fill().unwrap();
+        assert!(receiver.messages().unwrap().is_empty());
+
+        raw.write_all(b"llo").unwrap();
+        receiver.fill().unwrap();
+        assert_eq!(receiver.messages().unwrap(), [b"hello".to_vec()]);
+        sender.flush().unwrap();
+    }
+
+    #[test]
+    fn text_peers_are_read_line_by_line() {
+        let (mut sender, mut receiver) = pair(Framing::Lines);
+
+        sender.queue(b"status\nlog sl").unwrap();
+        sender.flush().unwrap();
+        receiver.fill().unwrap();
+
+        assert_eq!(receiver.messages().unwrap(), [b"status\n".to_vec()]);
+        assert_eq!(receiver.framing, Framing::Lines);
+    }
+
+    #[test]
+    fn oversized_frames_are_refused() {
+        let (mut sender, mut receiver) = pair(Framing::Frames);
+        let mut raw = sender.stream.try_clone().unwrap();
+
+        assert!(sender.queue(&vec![0; MAX_FRAME_LENGTH]).is_err());
+        raw.write_all(&(MAX_FRAME_LENGTH as u32).to_be_bytes())
+            .unwrap();
+        receiver.fill().unwrap();
+        assert!(receiver.messages().is_err());
+    }
+
+    #[test]
+    fn unsent_output_stays_queued() {
+        let (mut sender, mut receiver) = pair(Framing::Frames);
+        let message = vec![b'x'; MAX_FRAME_LENGTH / 2];
+
+        let mut sent = true;
+        for _ in 0..4 {
+            sender.queue(&message).unwrap();
+            sent = sender.flush().unwrap();
+        }
+        assert!(!sent && sender.has_pending_output());
+
+        let mut received = vec![];
+        while sender.has_pending_output() {
+            receiver.fill().unwrap();
+            received.extend(receiver.messages().unwrap());
+            sender.flush().unwrap();
+        }
+        receiver.fill().unwrap();
+        received.extend(receiver.messages().unwrap());
+        assert_eq!(received.len(), 4);
+    }
+}
@@ -1,3 +1,4 @@
 pub mod class;
+mod terminal;
 
 pub use class::Client;
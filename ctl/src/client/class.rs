@@ -2,9 +2,20 @@ use std::collections::VecDeque;
 use std::io::Write;
 use std::os::unix::net::UnixStream;
 
-use common::server::{Key, Server, ServerError, SERVER_KEY, STDIN_KEY};
-use common::{ClientState, Cmd, Request, CTL_SOCKET_PATH, DAEMON_SOCKET_PATH};
-use logger::{debug, info};
+use common::protocol::{Argument, RequestFrame, ResponseFrame, ResponseStatus};
+use common::server::{Framing, Key, Server, ServerError, SERVER_KEY, STDIN_KEY};
+use common::{
+    hex_encode,
+    ClientState,
+    Cmd,
+    Request,
+    RequestError,
+    CTL_SOCKET_PATH,
+    DAEMON_SOCKET_PATH,
+};
+use logger::{debug, info, warning};
+
+use super::terminal::{self, RawMode, DETACH_KEY, INTERRUPT_KEY};
 
 const BACKEND_KEY: Key = 1;
 
@@ -13,16 +24,20 @@ pub struct Client {
     pub backend: UnixStream,
     pub queries: VecDeque<String>,
     pub state:   ClientState,
+    terminal:    Option<RawMode>,
+    next_id:     u64,
 }
 
 impl Client {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Client {
         Client {
-            server:  Server::new(CTL_SOCKET_PATH),
-            backend: UnixStream::connect(DAEMON_SOCKET_PATH).unwrap(),
-            queries: VecDeque::new(),
-            state:   ClientState::default(),
+            server:   Server::new(CTL_SOCKET_PATH),
+            backend:  UnixStream::connect(DAEMON_SOCKET_PATH).unwrap(),
+            queries:  VecDeque::new(),
+            state:    ClientState::default(),
+            terminal: None,
+            next_id:  0,
         }
     }
 
@@ -32,13 +47,19 @@ impl Client {
         self.server.add_interest(Server::read_event(STDIN_KEY))?;
 
         self.backend.set_nonblocking(true)?;
-        self.server.clients.insert(1, self.backend.try_clone()?);
+        let backend = self.backend.try_clone()?;
+        self.server
+            .add_client(BACKEND_KEY, backend, Framing::Frames);
         self.server.add_interest(Server::write_event(BACKEND_KEY))?;
+        terminal::install_sigwinch_handler();
         Ok(())
     }
 
     pub fn serve_routine(&mut self) -> Result<(), ServerError> {
         self.server.epoll_wait()?;
+        if terminal::take_resize() && self.terminal.is_some() {
+            self.send_window_size()?;
+        }
         for ev in self.server.get_events() {
             let key: Key = ev.u64;
             if key == SERVER_KEY {
@@ -50,7 +71,7 @@ impl Client {
             if (ev.events & libc::EPOLLIN as u32) != 0 {
                 self.receive(key)?;
             }
-            if (ev.events & libc::EPOLLOUT as u32) != 0 && !self.queries.is_empty() {
+            if (ev.events & libc::EPOLLOUT as u32) != 0 {
                 self.query()?;
             }
         }
@@ -58,69 +79,191 @@ impl Client {
     }
 
     fn query(&mut self) -> Result<(), ServerError> {
-        if let Some(query) = self.queries.pop_front() {
-            self.backend.write_all(query.as_bytes())?;
-            let event = match self.state {
-                ClientState::Unattached => Server::read_event(BACKEND_KEY),
-                ClientState::Attached(_) => Server::fixed_read(BACKEND_KEY),
+        let mut sent = self.server.flush(BACKEND_KEY)?;
+        while sent {
+            let Some(query) = self.queries.pop_front() else {
+                break;
             };
-            self.server.modify_interest(event)?;
+            sent = self.server.send(BACKEND_KEY, query.as_bytes())?;
         }
-        Ok(())
+        if !sent {
+            return self.request_write(BACKEND_KEY);
+        }
+        let event = match self.state {
+            ClientState::Unattached => Server::read_event(BACKEND_KEY),
+            ClientState::Attached(_) => Server::fixed_read(BACKEND_KEY),
+        };
+        self.server.modify_interest(event)
     }
 
-    fn build_request(&self, k: Key, raw: &str) -> Request {
-        let mut request = Request::from(raw);
+    fn build_request(&self, k: Key, raw: &str) -> Result<Request, RequestError> {
+        let mut request = Request::parse(raw)?;
         request.client_key = k;
         request.state = self.state.clone();
-        if (request.state != ClientState::Unattached
-            && request.command != Cmd::Unattach)
-        {
+        if (request.state != ClientState::Unattached && !request.command.is_control()) {
             request.command = Cmd::Other(request.command.into());
         }
-        request
+        Ok(request)
     }
 
     fn receive(&mut self, key: Key) -> Result<(), ServerError> {
-        let msg = self.server.recv(key)?;
-        if msg.trim().is_empty() {
-            return Ok(());
+        if key == STDIN_KEY && self.terminal.is_some() {
+            return self.receive_keys();
         }
+        let messages = self.server.recv(key)?;
         match key {
             STDIN_KEY => {
-                let mut request = self.build_request(key, &msg);
-                if request.is_valid() {
-                    self.queries.push_back(msg);
-                    self.request_write(BACKEND_KEY)?;
-                    debug!("current queries: {:?}", &self.queries);
-                } else {
-                    println!("Error: {}", request.error.unwrap());
+                for line in messages {
+                    self.receive_line(&String::from_utf8_lossy(&line))?;
                 }
             }
             _ => {
-                println!("backend: {msg}");
-                if msg.ends_with("Attach successful!") {
-                    println!("frontend attached");
-                    self.state = ClientState::Attached("backend knows".into());
-                    let event = Server::fixed_read(BACKEND_KEY);
-                    self.server.modify_interest(event)?;
+                for message in messages {
+                    let frame = match std::str::from_utf8(&message) {
+                        Ok(line) => ResponseFrame::decode(line),
+                        Err(_) => Err("response is not UTF-8".into()),
+                    };
+                    match frame {
+                        Ok(frame) => self.handle_response(frame)?,
+                        Err(e) => warning!("{e}"),
+                    }
                 }
-                if msg.ends_with("Unattach successful!") {
-                    println!("frontend unattached");
-                    self.state = ClientState::Unattached;
-                    let event = Server::read_event(BACKEND_KEY);
-                    self.server.modify_interest(event)?;
+                // keep listening, the backend may be streaming
+                if self.state == ClientState::Unattached {
+                    match self.queries.is_empty() {
+                        true => self.server.modify_interest(Server::read_event(key))?,
+                        false => self.request_write(key)?,
+                    }
                 }
             }
         }
         Ok(())
     }
 
-    fn request_write(&mut self, key: Key) -> Result<(), ServerError> {
-        let event = match &self.state {
-            ClientState::Attached(_) => Server::read_write_event(key),
-            ClientState::Unattached => Server::write_event(key),
+    fn receive_line(&mut self, msg: &str) -> Result<(), ServerError> {
+        if msg.trim().is_empty() {
+            return Ok(());
+        }
+        let mut request = match self.build_request(STDIN_KEY, msg) {
+            Ok(request) => request,
+            Err(e) => {
+                println!("Error: {e}");
+                return Ok(());
+            }
         };
-        self.server.modify_interest(event)
+        if request.is_valid() {
+            // lines typed while attached are the program's input
+            match request.command {
+                Cmd::Other(_) => self.push_query(
+                    Cmd::Input,
+                    vec![Argument::Text(hex_encode(msg.as_bytes()))],
+                ),
+                _ => self.push_text(msg),
+            }
+            self.request_write(BACKEND_KEY)?;
+            debug!("current queries: {:?}", &self.queries);
+        } else {
+            println!("Error: {}", request.error.unwrap());
+        }
+        Ok(())
+    }
+
+    fn handle_response(&mut self, frame: ResponseFrame) -> Result<(), ServerError> {
+        let attached = frame.state != ClientState::Unattached;
+        if !attached && self.state != ClientState::Unattached {
+            self.terminal = None;
+        }
+        if self.terminal.is_some() {
+            print!("{}", frame.payload);
+            std::io::stdout().flush()?;
+            return Ok(());
+        }
+        match frame.status {
+            _ if frame.payload.is_empty() => {}
+            ResponseStatus::Ok => println!("backend #{}: {}", frame.id, frame.payload),
+            ResponseStatus::Error => {
+                println!("backend #{} error: {}", frame.id, frame.payload)
+            }
+        }
+        if attached && self.state == ClientState::Unattached {
+            println!("frontend attached");
+            self.state = frame.state;
+            let event = Server::fixed_read(BACKEND_KEY);
+            self.server.modify_interest(event)?;
+            if frame.terminal && terminal::is_terminal() {
+                self.enter_terminal()?;
+            }
+        } else if !attached && self.state != ClientState::Unattached {
+            println!("frontend unattached");
+            self.state = ClientState::Unattached;
+        }
+        Ok(())
+    }
+
+    /// Queues a command typed as text, sent as a JSON frame.
+    fn push_text(&mut self, line: &str) {
+        self.next_id += 1;
+        if let Some(frame) = RequestFrame::from_text(self.next_id, line) {
+            self.queries.push_back(frame.encode());
+        }
+    }
+
+    fn push_query(&mut self, command: Cmd, arguments: Vec<Argument>) {
+        self.next_id += 1;
+        let frame = RequestFrame::new(self.next_id, &String::from(command), arguments);
+        self.queries.push_back(frame.encode());
+    }
+
+    fn enter_terminal(&mut self) -> Result<(), ServerError> {
+        println!("press Ctrl-] to detach");
+        match RawMode::enable() {
+            Ok(raw) => self.terminal = Some(raw),
+            Err(e) => warning!("staying in line mode: {e}"),
+        }
+        self.send_window_size()
+    }
+
+    fn send_window_size(&mut self) -> Result<(), ServerError> {
+        if let Ok((rows, cols)) = terminal::window_size() {
+            let size =
+                vec![Argument::Number(rows.into()), Argument::Number(cols.into())];
+            self.push_query(Cmd::Winsize, size);
+            self.request_write(BACKEND_KEY)?;
+        }
+        Ok(())
+    }
+
+    /// Turns raw keystrokes into INPUT requests, except for the keys the
+    /// session itself reacts to.
+    fn receive_keys(&mut self) -> Result<(), ServerError> {
+        let keys = terminal::read_stdin()?;
+        self.server.modify_interest(Server::read_event(STDIN_KEY))?;
+        if keys.is_empty() {
+            self.push_query(Cmd::Unattach, vec![]);
+        }
+        let is_special = |key: &u8| *key == DETACH_KEY || *key == INTERRUPT_KEY;
+        for chunk in keys.split_inclusive(is_special) {
+            let (input, special) = match chunk.split_last() {
+                Some((key, input)) if is_special(key) => (input, Some(*key)),
+                _ => (chunk, None),
+            };
+            if !input.is_empty() {
+                let input = Argument::Text(hex_encode(input));
+                self.push_query(Cmd::Input, vec![input]);
+            }
+            match special {
+                Some(DETACH_KEY) => self.push_query(Cmd::Unattach, vec![]),
+                Some(INTERRUPT_KEY) => self.push_query(Cmd::Interrupt, vec![]),
+                _ => {}
+            }
+        }
+        if !self.queries.is_empty() {
+            self.request_write(BACKEND_KEY)?;
+        }
+        Ok(())
+    }
+
+    fn request_write(&mut self, key: Key) -> Result<(), ServerError> {
+        self.server.modify_interest(Server::read_write_event(key))
     }
 }
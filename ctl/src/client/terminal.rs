@@ -0,0 +1,65 @@
+use std::io;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use common::syscall;
+use libc::{termios, STDIN_FILENO, STDOUT_FILENO, TCSANOW};
+
+/// Ctrl-] leaves an attached terminal session, as in telnet.
+pub const DETACH_KEY: u8 = 0x1d;
+/// Ctrl-C, forwarded as SIGINT instead of being typed into the program.
+pub const INTERRUPT_KEY: u8 = 0x03;
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+/// Keeps stdin in raw mode while alive, restoring the original settings when
+/// dropped.
+pub struct RawMode {
+    original: termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        let mut original: termios = unsafe { MaybeUninit::zeroed().assume_init() };
+        syscall!(tcgetattr(STDIN_FILENO, &mut original))?;
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        syscall!(tcsetattr(STDIN_FILENO, TCSANOW, &raw))?;
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = syscall!(tcsetattr(STDIN_FILENO, TCSANOW, &self.original));
+    }
+}
+
+pub fn is_terminal() -> bool { unsafe { libc::isatty(STDIN_FILENO) == 1 } }
+
+pub fn window_size() -> io::Result<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { MaybeUninit::zeroed().assume_init() };
+    syscall!(ioctl(STDOUT_FILENO, libc::TIOCGWINSZ, &mut size))?;
+    Ok((size.ws_row, size.ws_col))
+}
+
+/// Reads whatever keys are available on stdin, one read at a time.
+pub fn read_stdin() -> io::Result<Vec<u8>> {
+    let mut buf = [0_u8; 1024];
+    let bytes = syscall!(read(STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()))?;
+    Ok(buf[..bytes as usize].to_vec())
+}
+
+extern "C" fn sigwinch_handler(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+pub fn install_sigwinch_handler() {
+    let mut action: libc::sigaction = unsafe { MaybeUninit::zeroed().assume_init() };
+    action.sa_sigaction = sigwinch_handler as *const () as usize;
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+    unsafe { libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut()) };
+}
+
+/// Whether the window changed size since the last call.
+pub fn take_resize() -> bool { RESIZED.swap(false, Ordering::Relaxed) }
@@ -1,4 +1,5 @@
 use std::error::Error;
+use std::process::ExitCode;
 
 use daemon::defs::DFL_CONFIG_FILE;
 use daemon::taskmaster::{Status, TaskMaster};
@@ -17,7 +18,7 @@ fn get_config_file() -> Result<String, Box<dyn Error>> {
     Ok(config_file)
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> Result<ExitCode, Box<dyn Error>> {
     let config_file = get_config_file()?;
     let mut taskmaster = TaskMaster::new();
 
@@ -27,10 +28,14 @@ fn main() -> Result<(), Box<dyn Error>> {
             Status::Starting => taskmaster.build(&config_file)?,
             Status::Reloading => taskmaster.reload()?,
             Status::Active => info!("All Good!"),
+            Status::Exiting => info!("Shutting down..."),
         }
         // while taskmaster.serve_routine().is_ok() {}
         '_main: loop {
             taskmaster.backend.update_processes_status();
+            if taskmaster.can_exit() {
+                return Ok(taskmaster.exit_code());
+            }
             taskmaster.generate_responses();
             match taskmaster.serve_routine() {
                 Ok(_) => match taskmaster.status {
@@ -14,7 +14,9 @@ fn main() {
         .args(program.args.to_vec())
         .env("ANSWER", "42")
         .spawn()
-        .expect("fuck");
+        .expect("spawn echo")
+        .wait()
+        .expect("wait echo");
     // std::io::stdout()
     //     .write_all(&output.unwrap().stdout)
     //     .unwrap();
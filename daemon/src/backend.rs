@@ -1,10 +1,27 @@
 mod class;
+mod events;
 mod handlers;
+mod hooks;
+mod listener;
+mod logs;
+mod notify;
 pub(super) mod print_functions;
 mod process;
 mod program;
+mod readiness;
+mod reload;
+mod restart;
+mod shutdown;
+mod sockets;
+mod status;
+mod target;
+mod watch;
 
 pub use class::BackEnd;
 pub use common::ClientState;
+pub use events::EventLog;
+pub use logs::{LogQuery, Stream};
 pub use process::{Process, ProcessStatus};
 pub use program::Program;
+pub use reload::ConfigDiff;
+pub use target::Target;
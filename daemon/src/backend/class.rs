@@ -1,20 +1,49 @@
 use std::collections::HashMap;
 use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
 use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Instant;
 use std::{env, fs};
 
-use common::{CmdHandler, Request, Response};
-use logger::{debug, info};
+use common::{
+    ClientState,
+    Cmd,
+    CmdError,
+    CmdErrorKind,
+    CmdHandler,
+    Key,
+    Request,
+    Response,
+};
+use logger::{debug, error, warning};
 
 use super::print_functions::{print_processes, print_programs};
-use super::{Process, Program};
-use crate::config::{ConfigError, ProgramConfig};
+use super::{EventLog, LogQuery, Process, Program, Stream, Target};
+use crate::config::ProgramConfig;
+use crate::watcher::Watcher;
 use crate::TaskMasterConfig;
 
 #[derive(Default)]
 pub struct BackEnd {
-    pub config:   TaskMasterConfig,
-    pub programs: HashMap<String, Program>,
+    pub config:           TaskMasterConfig,
+    /// Where the config was read from, changes saved at runtime go there
+    pub config_file:      String,
+    pub programs:         HashMap<String, Program>,
+    /// Programs removed or replaced by a reload, until they stopped
+    pub retiring:         Vec<Program>,
+    pub events:           EventLog,
+    /// Watches the config file while `watch_config` is set
+    pub config_watcher:   Option<Watcher>,
+    /// Watches the `watch` paths of the programs, if any
+    pub watcher:          Option<Watcher>,
+    /// Programs by the watched path they restart on
+    pub watched:          HashMap<PathBuf, Vec<String>>,
+    /// When the watched files of a program changed last, until it restarts
+    pub changed:          HashMap<String, Instant>,
+    /// Watchers were created since the daemon added them to its epoll set
+    pub watchers_changed: bool,
 }
 
 impl BackEnd {
@@ -26,21 +55,34 @@ impl BackEnd {
     }
 
     pub fn handle_request(&mut self, request: &mut Request) -> Response {
-        let msg = self.handle(request).unwrap_or_else(|x| x.into());
+        let result = self.handle(request);
         Response {
-            message:    msg,
-            finished:   request.finished,
-            client_key: request.client_key,
+            terminal: self.is_on_terminal(&request.state),
+            ..Response::new(request, result)
         }
     }
 
+    fn is_on_terminal(&self, state: &ClientState) -> bool {
+        let ClientState::Attached(target) = state else {
+            return false;
+        };
+        target
+            .parse::<Target>()
+            .ok()
+            .and_then(|target| self.programs.get(&target.program))
+            .is_some_and(|program| program.config.tty)
+    }
+
     pub fn start(&mut self) {
         print_programs("initial programs", &self.config.programs);
         self.programs = Self::create_programs(&self.config.programs);
         self.create_log_files();
         for p in self.programs.values_mut() {
             p.create_output_files();
+            p.open_sockets(&mut vec![]);
         }
+        self.watch_config();
+        self.watch_programs();
         self.create_startup_processes();
 
         print_processes(&self.programs);
@@ -57,52 +99,106 @@ impl BackEnd {
         let mut log_file = log_dir.clone();
         log_file.push("log.txt");
         opts.open(log_file).unwrap();
+    }
 
+    /// Drains the terminals of tty programs into their stdout log and copies
+    /// the output to the clients attached to them.
+    pub fn handle_outputs(&mut self, attached: &[(Key, Target)]) -> Vec<Response> {
+        let mut responses = vec![];
         for program in self.programs.values() {
-            let name = format!("{}/{}", log_dir.display(), program.config.command);
-            let program_dir = PathBuf::from(name);
-            for i in 0..program.config.processes {
-                if program.config.command.is_empty() {
+            for (i, process) in program.processes.iter().enumerate() {
+                let Some(terminal) = &process.terminal else {
                     continue;
-                }
-                let mut process_dir = program_dir.clone();
-                process_dir.push(format!("p{i}/"));
-                if fs::metadata(&process_dir).is_err() {
-                    fs::create_dir_all(&process_dir).unwrap();
                 };
-                let mut stdout = process_dir.clone();
-                stdout.push("stdout");
-                let mut stderr = process_dir.clone();
-                stderr.push("stderr");
-                opts.open(stdout).unwrap();
-                opts.open(stderr).unwrap();
+                let output = match terminal.read_available() {
+                    Ok(output) if output.is_empty() => continue,
+                    Ok(output) => output,
+                    Err(e) => {
+                        warning!(
+                            "{}[{i}]: terminal read failed: {e}",
+                            program.config_name
+                        );
+                        continue;
+                    }
+                };
+                if let Some(Some(file)) = program.stdout.get(i) {
+                    (&*file)
+                        .write_all(&output)
+                        .unwrap_or_else(|e| error!("{e}"));
+                }
+                let message = String::from_utf8_lossy(&output).to_string();
+                for (key, target) in attached {
+                    if target.program == program.config_name
+                        && target.instance.is_none_or(|instance| instance == i)
+                    {
+                        responses.push(Response {
+                            message: message.clone(),
+                            finished: true,
+                            client_key: *key,
+                            state: ClientState::Attached(target.to_string()),
+                            terminal: true,
+                            ..Default::default()
+                        });
+                    }
+                }
             }
         }
-    }
-
-    pub fn handle_outputs(&mut self) -> Vec<Response> {
-        // mock
-        vec![Response {
-            message:    String::from("PUDIM\n"),
-            client_key: 43,
-            finished:   true,
-        }]
+        responses
     }
 
     pub fn update_processes_status(&mut self) {
+        self.reap_children();
         self.programs
             .iter_mut()
-            .for_each(|(_, program)| program.update_process_status())
+            .for_each(|(_, program)| program.update_process_status());
+        self.update_retiring_programs();
+        self.restart_changed_programs();
     }
 
     pub fn handle_dead_processes(&mut self) {
-        self.programs.iter_mut().for_each(|(_, program)| {
-            program.update_process_status();
-        });
-
+        self.update_processes_status();
         self.dump_processes_status();
     }
 
+    /// Collects the exit of every child that ended and hands it to the
+    /// process or the hook it was.
+    fn reap_children(&mut self) {
+        loop {
+            let mut status = 0;
+            let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+            // 0 while the children left are running, -1 once there are none
+            if pid <= 0 {
+                return;
+            }
+            let status = ExitStatus::from_raw(status);
+            let pid = pid as u32;
+            // hooks first, a process keeps the pid of its last child
+            let hook = self
+                .processes_mut()
+                .flat_map(|process| process.hooks.iter_mut())
+                .find(|hook| hook.pid() == pid);
+            if let Some(hook) = hook {
+                hook.reaped(status);
+                continue;
+            }
+            match self
+                .processes_mut()
+                .find(|process| process.pid() == Some(pid))
+            {
+                Some(process) => process.reaped(status),
+                None => debug!("reaped {pid}, {status}, no process left for it"),
+            }
+        }
+    }
+
+    /// Every process, those of the retiring programs included.
+    fn processes_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.programs
+            .values_mut()
+            .chain(self.retiring.iter_mut())
+            .flat_map(|program| program.processes.iter_mut())
+    }
+
     fn create_programs(
         program_configs: &HashMap<String, ProgramConfig>,
     ) -> HashMap<String, Program> {
@@ -115,89 +211,59 @@ impl BackEnd {
     }
 
     fn create_startup_processes(&mut self) {
-        self.programs
-            .iter_mut()
-            .filter(|(_, program)| program.config.run_at_startup)
-            .for_each(|(_, program)| {
+        for name in self.config.start_order() {
+            let Some(program) = self.programs.get_mut(&name) else {
+                continue;
+            };
+            if program.config.run_at_startup {
                 program.processes =
                     Self::create_processes(program, program.config.processes);
 
                 program.update_process_status();
-            });
-    }
-
-    pub fn format_status(&self) -> String {
-        let mut dump = String::new();
-        for (_, program) in self.programs.iter() {
-            for (i, process) in program.processes.iter().enumerate() {
-                dump.push_str(&format!(
-                    "{:15}[{}]: {}\n",
-                    program.config_name, i, process
-                ));
             }
         }
-        dump.pop();
-        dump
     }
 
     fn create_processes(program: &mut Program, count: usize) -> Vec<Process> {
-        (0..count)
-            .map(|_| Process::start(&mut program.command))
-            .collect()
-    }
-
-    pub fn update(&mut self, new_config: TaskMasterConfig) -> Result<(), ConfigError> {
-        if self.config != new_config {
-            info!("Updating config");
-            self.update_state(new_config);
-        } else {
-            info!("No changes detected.");
-        }
-
-        Ok(())
-    }
-
-    fn update_state(&mut self, new_config: TaskMasterConfig) {
-        let programs_to_kill = get_diff(&self.config.programs, &new_config.programs);
-        print_programs("programs to kill", &programs_to_kill);
-        let programs_to_spawn = get_diff(&new_config.programs, &self.config.programs);
-        print_programs("programs to spawn", &programs_to_spawn);
-
-        self.config = new_config;
+        (0..count).map(|i| program.spawn(i)).collect()
     }
 
     pub fn dump_processes_status(&self) {
         debug!("{}", print_processes(&self.programs));
     }
-}
 
-fn get_diff(
-    first_list: &HashMap<String, ProgramConfig>,
-    second_list: &HashMap<String, ProgramConfig>,
-) -> HashMap<String, ProgramConfig> {
-    first_list
-        .iter()
-        .filter(|&(key_in_first, config)| {
-            !second_list.contains_key(key_in_first)
-                || has_major_changes(config, &second_list[key_in_first])
-        })
-        .map(|(key, program)| (key.to_owned(), program.clone()))
-        .collect()
-}
+    /// File a stream of the target is logged to, instance 0 unless given.
+    pub fn log_path(
+        &self,
+        target: &Target,
+        stream: Stream,
+    ) -> Result<PathBuf, CmdError> {
+        let program = match self.programs.get(&target.program) {
+            Some(program) => program,
+            None => return Err(CmdErrorKind::NotFound(target.program.clone()).into()),
+        };
+        let instance = target.instance.unwrap_or(0);
+        if instance >= program.config.processes {
+            return Err(format!("{target} does not exist").into());
+        }
+        match program.output_path(stream, instance) {
+            Some(path) => Ok(path),
+            None => Err(format!("{target} {stream} is discarded").into()),
+        }
+    }
 
-fn has_major_changes(first: &ProgramConfig, second: &ProgramConfig) -> bool {
-    first.command != second.command
-        || first.args != second.args
-        // || first.status != second.status
-        // || first.processes != second.processes
-        // || first.run_at_startup != second.run_at_startup
-        // || first.retry_start_count != second.retry_start_count
-        // || first.restart != second.restart
-        // || first.graceful_exit != second.graceful_exit
-        // || first.ttk != second.ttk
-        // || first.success_codes != second.success_codes.clone()
-        // || first.succesful_start_after != second.succesful_start_after
-        || first.workdir != second.workdir
-        || first.environment_variables != second.environment_variables
-        || first.umask != second.umask
+    /// Whether a streaming request has something new to send.
+    pub fn has_pending_output(&self, request: &Request) -> bool {
+        let Some(cursor) = request.cursor else {
+            return false;
+        };
+        match request.command {
+            Cmd::Log => LogQuery::parse(&request.arguments)
+                .and_then(|query| self.log_path(&query.target, query.stream))
+                .and_then(|path| fs::metadata(path).map_err(|e| e.to_string().into()))
+                .is_ok_and(|metadata| metadata.len() != cursor),
+            Cmd::Subscribe => !self.subscribed_events(request).is_empty(),
+            _ => false,
+        }
+    }
 }
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::time::UNIX_EPOCH;
+
+use common::{Event, Request};
+use logger::warning;
+
+use super::BackEnd;
+
+/// Events kept for subscribers that fall behind.
+pub(super) const MAX_EVENT_BACKLOG: usize = 1024;
+
+/// Events numbered in the order they were published, subscribers keep the
+/// number of the next one they expect.
+#[derive(Debug, Default)]
+pub struct EventLog {
+    events: VecDeque<Event>,
+    end:    u64,
+}
+
+impl EventLog {
+    pub fn push(&mut self, event: Event) {
+        self.events.push_back(event);
+        self.end += 1;
+        if self.events.len() > MAX_EVENT_BACKLOG {
+            self.events.pop_front();
+        }
+    }
+
+    /// Number the next event will get.
+    pub fn end(&self) -> u64 { self.end }
+
+    /// Events published since `cursor` with their number, those already
+    /// forgotten are skipped.
+    pub fn since(&self, cursor: u64) -> impl Iterator<Item = (u64, &Event)> {
+        let first = self.end - self.events.len() as u64;
+        if cursor < first {
+            warning!("subscriber missed {} events", first - cursor);
+        }
+        (first..)
+            .zip(self.events.iter())
+            .skip(cursor.saturating_sub(first) as usize)
+    }
+}
+
+impl BackEnd {
+    pub fn publish(&mut self, event: Event) { self.events.push(event); }
+
+    /// Publishes the state changes the processes went through since the last
+    /// call, oldest first.
+    pub fn collect_events(&mut self) {
+        let mut events = vec![];
+        for program in self.programs.values_mut() {
+            for (instance, process) in program.processes.iter_mut().enumerate() {
+                for transition in process.transitions.drain(..) {
+                    let at = transition.at.duration_since(UNIX_EPOCH);
+                    events.push(Event::Process {
+                        program: program.config_name.clone(),
+                        instance,
+                        pid: transition.pid,
+                        state: transition.state,
+                        at: at.map_or(0, |d| d.as_secs()),
+                    });
+                }
+            }
+        }
+        events.sort_by_key(|event| match event {
+            Event::Process { at, .. } => *at,
+            _ => 0,
+        });
+        events.into_iter().for_each(|event| self.publish(event));
+    }
+
+    /// Events a SUBSCRIBE request has not been sent yet.
+    pub fn subscribed_events(&self, request: &Request) -> Vec<&Event> {
+        let Some(cursor) = request.cursor else {
+            return vec![];
+        };
+        self.events
+            .since(cursor)
+            .map(|(_, event)| event)
+            .filter(|event| self.event_matches(event, &request.arguments))
+            .collect()
+    }
+
+    /// Whether an event concerns one of the programs or groups named, all
+    /// of them when none is. Daemon wide events concern everyone.
+    pub fn event_matches(&self, event: &Event, filters: &[String]) -> bool {
+        let Event::Process { program, .. } = event else {
+            return true;
+        };
+        filters.is_empty()
+            || filters.iter().any(|filter| {
+                filter == program
+                    || self
+                        .programs
+                        .get(program)
+                        .is_some_and(|p| p.config.group.as_ref() == Some(filter))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribers_behind_the_backlog_resume_at_the_oldest_event() {
+        let mut log = EventLog::default();
+        for _ in 0..MAX_EVENT_BACKLOG + 2 {
+            log.push(Event::Shutdown);
+        }
+
+        assert_eq!(log.end(), MAX_EVENT_BACKLOG as u64 + 2);
+        assert_eq!(log.since(0).count(), MAX_EVENT_BACKLOG);
+        assert_eq!(log.since(log.end() - 1).count(), 1);
+        assert_eq!(log.since(log.end()).count(), 0);
+    }
+}
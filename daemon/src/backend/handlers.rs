@@ -1,5 +1,21 @@
-use common::{ClientState, Cmd, CmdError, CmdErrorKind, CmdHandler, Request};
+use std::io;
 
+use common::{
+    hex_decode,
+    hex_encode,
+    ClientState,
+    Cmd,
+    CmdError,
+    CmdErrorKind,
+    CmdHandler,
+    Encoding,
+    Request,
+};
+
+use super::logs::{self, LogQuery, ReadQuery};
+use super::{Process, Program, Target};
+use crate::config::edit::save_process_count;
+use crate::config::Signal;
 use crate::BackEnd;
 
 impl CmdHandler for BackEnd {
@@ -8,8 +24,19 @@ impl CmdHandler for BackEnd {
             Cmd::Log => self.log(request),
             Cmd::Status => self.status(request),
             Cmd::Head => self.head(request),
+            Cmd::Read => self.read(request),
             Cmd::Attach => self.attach(request),
             Cmd::Unattach => self.unattach(request),
+            Cmd::Input => self.input(request),
+            Cmd::Winsize => self.winsize(request),
+            Cmd::Interrupt => self.interrupt(request),
+            Cmd::Subscribe => self.subscribe(request),
+            Cmd::Signal => self.signal(request),
+            Cmd::Scale => self.scale(request),
+            Cmd::Reload => self.reload(request),
+            Cmd::Restart => self.restart(request),
+            Cmd::Cancel => Err("CANCEL is answered by the connection".into()),
+            Cmd::Shutdown => Err("SHUTDOWN is answered by the daemon".into()),
             Cmd::Other(_) => self.other(request),
         }
     }
@@ -21,17 +48,30 @@ impl CmdHandler for BackEnd {
             Some(value) => value,
             None => {
                 let kind = CmdErrorKind::InvalidArguments;
-                return Err(format!("Attach failed: {kind}").into());
+                return Err(CmdError::with_kind("Attach failed", kind));
+            }
+        };
+        let target: Target = command_name.parse()?;
+        let program = match self.programs.get(&target.program) {
+            Some(program) => program,
+            None => {
+                let kind = CmdErrorKind::NotFound(target.program);
+                return Err(CmdError::with_kind("Attach failed", kind));
             }
         };
-        if !self.programs.contains_key(command_name) {
-            let kind = CmdErrorKind::NotFound(command_name.into());
-            return Err(format!("Attach failed: {kind}").into());
+        if target
+            .instance
+            .is_some_and(|i| i >= program.config.processes)
+        {
+            return Err(format!("Attach failed: {target} does not exist").into());
         }
 
         let state = ClientState::Attached(command_name.into());
         request.state = state.clone();
-        Ok("Attach successful!".into())
+        match program.config.tty {
+            true => Ok("Attach successful! (tty)".into()),
+            false => Ok("Attach successful!".into()),
+        }
     }
 
     fn unattach(&mut self, request: &mut Request) -> Result<String, CmdError> {
@@ -47,21 +87,378 @@ impl CmdHandler for BackEnd {
 
     fn log(&self, request: &mut Request) -> Result<String, CmdError> {
         request.finished = true;
-        Ok("todo!(log)".into())
+
+        let query = LogQuery::parse(&request.arguments)?;
+        let path = self.log_path(&query.target, query.stream)?;
+        let read = match request.cursor {
+            Some(cursor) => logs::read_from(&path, cursor),
+            None => logs::tail(&path, query.lines),
+        };
+        let (content, end) = read.map_err(|e| format!("{}: {e}", path.display()))?;
+        if query.follow {
+            request.finished = false;
+            request.cursor = Some(end);
+        }
+        Ok(content)
     }
 
     fn head(&self, request: &mut Request) -> Result<String, CmdError> {
         request.finished = true;
-        Ok("todo!(head)".into())
+
+        let query = LogQuery::parse(&request.arguments)?;
+        if query.follow {
+            return Err("HEAD cannot follow a log".into());
+        }
+        let path = self.log_path(&query.target, query.stream)?;
+        logs::head(&path, query.lines)
+            .map_err(|e| format!("{}: {e}", path.display()).into())
+    }
+
+    /// The bytes of the range hex encoded, exactly as logged, a range may
+    /// cut a character and a log hold binary output.
+    fn read(&self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = true;
+
+        let query = ReadQuery::parse(&request.arguments)?;
+        let path = self.log_path(&query.target, query.stream)?;
+        match logs::read_range(&path, query.offset, query.length) {
+            Ok(bytes) => Ok(hex_encode(&bytes)),
+            Err(e) => Err(format!("{}: {e}", path.display()).into()),
+        }
     }
 
     fn status(&self, request: &mut Request) -> Result<String, CmdError> {
         request.finished = true;
-        Ok(self.format_status())
+        self.format_status(&request.arguments)
+    }
+
+    fn input(&mut self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = true;
+
+        let input = match request.arguments.first().and_then(|arg| hex_decode(arg)) {
+            Some(input) => input,
+            None => return Err("INPUT requires hex encoded bytes".into()),
+        };
+        self.write_input(request, &input)?;
+        Ok(String::new())
+    }
+
+    fn winsize(&self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = true;
+
+        let size: Vec<u16> = request
+            .arguments
+            .iter()
+            .filter_map(|arg| arg.parse().ok())
+            .collect();
+        let [rows, cols] = size[..] else {
+            return Err("WINSIZE requires <rows> <cols>".into());
+        };
+        let (target, program) = self.attached_program(request)?;
+        for (i, process) in target_processes(&target, program) {
+            let result = match &process.terminal {
+                Some(terminal) => terminal.resize(rows, cols),
+                None => Err(io::Error::other("not a terminal")),
+            };
+            if let Err(e) = result {
+                return Err(format!("{target}[{i}]: resize failed: {e}").into());
+            }
+        }
+        Ok(String::new())
+    }
+
+    fn interrupt(&self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = true;
+
+        let (target, program) = self.attached_program(request)?;
+        for (i, process) in target_processes(&target, program) {
+            let result = match &process.terminal {
+                Some(terminal) => terminal.interrupt(),
+                None => Err(io::Error::other("not a terminal")),
+            };
+            if let Err(e) = result {
+                return Err(format!("{target}[{i}]: interrupt failed: {e}").into());
+            }
+        }
+        Ok(String::new())
+    }
+
+    fn subscribe(&self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = false;
+
+        let Some(cursor) = request.cursor else {
+            for filter in &request.arguments {
+                if !self.programs.contains_key(filter) && !self.is_group(filter) {
+                    request.finished = true;
+                    return Err(CmdErrorKind::NotFound(filter.clone()).into());
+                }
+            }
+            request.cursor = Some(self.events.end());
+            return Ok("subscribed".into());
+        };
+        let json = request.encoding == Encoding::Json;
+        let events = self.subscribed_events(request);
+        let lines = events.iter().map(|event| event.encode(json)).collect();
+        request.cursor = Some(self.events.end().max(cursor));
+        Ok(lines)
+    }
+
+    fn signal(&self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = true;
+
+        let (name, targets) = match &request.arguments[..] {
+            [name, targets @ ..] if !targets.is_empty() => (name, targets),
+            _ => return Err("SIGNAL requires <signal> <target>...".into()),
+        };
+        let signal = name.parse::<Signal>().map_err(|e| e.to_string())?;
+
+        let mut report = vec![];
+        let mut failed = false;
+        for target in self.resolve_targets(targets)? {
+            let program = &self.programs[&target.program];
+            for (i, process) in target_processes(&target, program) {
+                let name = format!("{}:{i}", target.program);
+                let result = match process.pid() {
+                    Some(pid) => process
+                        .send_signal(signal)
+                        .map(|()| format!("{name} pid {pid}: {signal:?} sent"))
+                        .map_err(|e| format!("{name} pid {pid}: {e}")),
+                    None => Err(format!("{name}: not running")),
+                };
+                failed |= result.is_err();
+                report.push(result.unwrap_or_else(|e| e));
+            }
+        }
+        match failed {
+            true => Err(report.join("\n").into()),
+            false => Ok(report.join("\n")),
+        }
     }
 
-    fn other(&self, request: &mut Request) -> Result<String, CmdError> {
+    fn scale(&mut self, request: &mut Request) -> Result<String, CmdError> {
         request.finished = true;
-        Ok("todo!(other)".into())
+
+        let save = request.arguments.iter().any(|arg| arg == "--save");
+        let arguments: Vec<&String> = request
+            .arguments
+            .iter()
+            .filter(|arg| *arg != "--save")
+            .collect();
+        let [name, count] = arguments[..] else {
+            return Err("SCALE requires <program> <count|+n|-n> [--save]".into());
+        };
+        let Some(program) = self.programs.get_mut(name) else {
+            return Err(CmdErrorKind::NotFound(name.clone()).into());
+        };
+        let current = program.config.processes;
+        let Some(count) = scaled_count(current, count) else {
+            return Err(format!("invalid process count: {count}").into());
+        };
+        program.scale(count).map_err(|e| format!("{name}: {e}"))?;
+
+        let mut message = format!("{name}: {current} -> {count} processes");
+        if save {
+            save_process_count(&self.config_file, name, count)
+                .map_err(|e| format!("{message}, not saved: {e}"))?;
+            if let Some(config) = self.config.programs.get_mut(name) {
+                config.processes = count;
+            }
+            message.push_str(&format!(", saved to {}", self.config_file));
+        }
+        Ok(message)
+    }
+
+    fn reload(&mut self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = true;
+
+        let dry_run = match request.arguments.as_slice() {
+            [] => false,
+            [flag] if flag == "--dry-run" => true,
+            _ => return Err("RELOAD takes no argument but --dry-run".into()),
+        };
+        let config = self.read_config().map_err(|e| e.to_string())?;
+        let diff = self.reload_config(config, dry_run);
+        match dry_run {
+            true => Ok(format!("dry run, nothing applied\n{diff}")),
+            false => Ok(diff.to_string()),
+        }
+    }
+
+    /// Restarts every instance of a program at once, or a batch at a time
+    /// with `--rolling`, each batch waiting for the previous one to be active.
+    fn restart(&mut self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = true;
+
+        let usage = "RESTART requires <program> [--rolling] [--batch <n>]";
+        let (mut name, mut rolling, mut batch) = (None, false, None);
+        let mut arguments = request.arguments.iter();
+        while let Some(argument) = arguments.next() {
+            match argument.as_str() {
+                "--rolling" => rolling = true,
+                "--batch" => {
+                    let size = arguments.next().ok_or(usage)?;
+                    match size.parse::<usize>() {
+                        Ok(size) if size > 0 => batch = Some(size),
+                        _ => return Err(format!("invalid batch size: {size}").into()),
+                    }
+                }
+                _ if name.is_none() => name = Some(argument),
+                _ => return Err(usage.into()),
+            }
+        }
+        let Some(name) = name else {
+            return Err(usage.into());
+        };
+        let Some(program) = self.programs.get_mut(name) else {
+            return Err(CmdErrorKind::NotFound(name.clone()).into());
+        };
+        let count = program.processes.len();
+        if count == 0 {
+            return Err(format!("{name} was never started").into());
+        }
+        // a batch size alone asks for a rolling restart
+        let batch = match (rolling, batch) {
+            (_, Some(size)) => size,
+            (true, None) => 1,
+            (false, None) => count,
+        };
+        program.restart_instances(batch);
+        Ok(format!(
+            "{name}: restarting {count} processes, {} at a time",
+            batch.min(count)
+        ))
+    }
+
+    fn other(&mut self, request: &mut Request) -> Result<String, CmdError> {
+        request.finished = true;
+
+        let mut line = request.raw.clone();
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+        self.write_input(request, line.as_bytes())?;
+        Ok(String::new())
+    }
+}
+
+impl BackEnd {
+    fn attached_program(
+        &self,
+        request: &Request,
+    ) -> Result<(Target, &Program), CmdError> {
+        let target: Target = match &request.state {
+            ClientState::Attached(target) => target.parse()?,
+            ClientState::Unattached => {
+                return Err(CmdErrorKind::NotFound(request.command.to_string()).into())
+            }
+        };
+        match self.programs.get(&target.program) {
+            Some(program) => Ok((target, program)),
+            None => Err(CmdErrorKind::NotFound(target.program).into()),
+        }
+    }
+
+    /// Targets named by a client: `all`, groups, programs and instances,
+    /// sorted by program.
+    fn resolve_targets(&self, names: &[String]) -> Result<Vec<Target>, CmdError> {
+        let mut targets = vec![];
+        for name in names {
+            if name == "all" || self.is_group(name) {
+                targets.extend(
+                    self.programs
+                        .values()
+                        .filter(|p| {
+                            name == "all" || p.config.group.as_ref() == Some(name)
+                        })
+                        .map(|p| Target {
+                            program:  p.config_name.clone(),
+                            instance: None,
+                        }),
+                );
+                continue;
+            }
+            let target: Target = name.parse()?;
+            let Some(program) = self.programs.get(&target.program) else {
+                return Err(CmdErrorKind::NotFound(target.program).into());
+            };
+            if target
+                .instance
+                .is_some_and(|i| i >= program.processes.len())
+            {
+                return Err(format!("{target} does not exist").into());
+            }
+            targets.push(target);
+        }
+        targets.sort_by(|a, b| a.program.cmp(&b.program));
+        targets.dedup();
+        Ok(targets)
+    }
+
+    fn write_input(&mut self, request: &Request, input: &[u8]) -> Result<(), CmdError> {
+        let (target, program) = self.attached_program(request)?;
+        if !program.config.allow_stdin {
+            return Err(format!("{target} does not accept input").into());
+        }
+        let program = self.programs.get_mut(&target.program).unwrap();
+        let processes = program.processes.iter_mut().enumerate();
+        for (i, process) in processes
+            .filter(|(i, _)| target.instance.is_none_or(|instance| instance == *i))
+        {
+            if let Err(e) = process.write_stdin(input) {
+                return Err(format!("{target}[{i}]: input not delivered: {e}").into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A count given as is, or relative to the current one with a sign.
+fn scaled_count(current: usize, count: &str) -> Option<usize> {
+    match count.as_bytes().first()? {
+        b'+' => current.checked_add(count[1..].parse().ok()?),
+        b'-' => current.checked_sub(count[1..].parse().ok()?),
+        _ => count.parse().ok(),
+    }
+}
+
+fn target_processes<'a>(
+    target: &'a Target,
+    program: &'a Program,
+) -> impl Iterator<Item = (usize, &'a Process)> {
+    program
+        .processes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| target.instance.is_none_or(|instance| instance == *i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::structs::IOHandler;
+    use crate::config::ProgramConfig;
+
+    #[test]
+    fn read_returns_the_bytes_of_a_range_cutting_a_character() {
+        let stdout = std::env::temp_dir().join("taskmaster_read_utf8");
+        std::fs::write(&stdout, "caf\u{e9}\n").unwrap();
+        let mut config = ProgramConfig::new();
+        config.stdout = IOHandler::FILE(stdout.display().to_string());
+        let mut backend = BackEnd::default();
+        let name = "web".to_string();
+        backend
+            .programs
+            .insert(name.clone(), Program::build_from((&name, &config)));
+        let mut request = Request {
+            command: Cmd::Read,
+            arguments: ["web", "stdout", "2", "2"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+
+        let payload = backend.handle(&mut request).unwrap();
+
+        // 'f' and the first byte of 'é'
+        assert_eq!(hex_decode(&payload).unwrap(), [b'f', 0xc3]);
+        std::fs::remove_file(stdout).unwrap();
     }
 }
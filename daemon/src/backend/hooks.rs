@@ -0,0 +1,378 @@
+//! Commands run around the life of a process: `pre_start` before it is
+//! spawned, `post_start` once it is active, `pre_stop` before it is asked to
+//! exit and `post_stop` after it exited.
+//!
+//! Hooks run with `sh -c` in the workdir and environment of the program, plus
+//! TASKMASTER_PROGRAM and TASKMASTER_INSTANCE, and write to the output files
+//! of the instance. They are children of the daemon like the processes, reaped
+//! with them: the process moves on once its hook exited, and a hook still
+//! running after `hook_timeout` seconds is killed and counts as failed.
+//!
+//! A `stop_command` runs the same way, with `{pid}` standing for the pid of
+//! the process to stop. It is given the wait of the first stop step, `ttk`
+//! unless `stop_signals` are set, after which the stop ladder goes on.
+
+use std::fs::File;
+use std::io::Error;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+#[cfg(not(test))]
+use std::time::{Duration, Instant};
+use std::{fmt, mem};
+
+use logger::{info, warning};
+
+#[cfg(test)]
+use super::process::tests::{Duration, Instant};
+use super::{Process, ProcessStatus, Program};
+use crate::signal_handling::unblock_signals;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    PreStart,
+    PostStart,
+    PreStop,
+    PostStop,
+    StopCommand,
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hook::PreStart => write!(f, "pre_start"),
+            Hook::PostStart => write!(f, "post_start"),
+            Hook::PreStop => write!(f, "pre_stop"),
+            Hook::PostStop => write!(f, "post_stop"),
+            Hook::StopCommand => write!(f, "stop_command"),
+        }
+    }
+}
+
+/// A hook running for an instance, until it exited or ran out of time.
+#[derive(Debug)]
+pub struct RunningHook {
+    pub hook:    Hook,
+    child:       Child,
+    started_at:  Instant,
+    timeout:     Duration,
+    /// How it exited, once the daemon reaped it
+    exit_status: Option<ExitStatus>,
+}
+
+impl RunningHook {
+    pub fn pid(&self) -> u32 { self.child.id() }
+
+    /// Hands the hook the exit status the daemon reaped for its pid.
+    pub fn reaped(&mut self, status: ExitStatus) { self.exit_status = Some(status); }
+
+    /// Kills a hook given up on, it is then reaped as a child no process
+    /// claims. One already reaped is left alone, its pid may be reused.
+    pub fn kill(&mut self) {
+        if self.exit_status.is_some() {
+            return;
+        }
+        if let Err(e) = self.child.kill() {
+            warning!("{} hook not killed: {e}", self.hook);
+        }
+    }
+
+    /// How the hook ended, None while it runs. One out of time is killed.
+    fn result(&mut self) -> Option<Result<(), Error>> {
+        if let Some(status) = self.exit_status {
+            return Some(match status.success() {
+                true => Ok(()),
+                false => Err(Error::other(status.to_string())),
+            });
+        }
+        if self.started_at.elapsed() < self.timeout {
+            return None;
+        }
+        self.kill();
+        let message = format!("timed out after {}s", self.timeout.as_secs());
+        Some(Err(Error::other(message)))
+    }
+}
+
+impl Process {
+    /// Whether a hook of the process is running.
+    pub fn runs_hook(&self, hook: Hook) -> bool {
+        self.hooks.iter().any(|running| running.hook == hook)
+    }
+}
+
+impl Program {
+    /// Starts a hook of an instance, None if the program has none.
+    pub fn start_hook(
+        &self,
+        hook: Hook,
+        instance: usize,
+    ) -> Result<Option<RunningHook>, Error> {
+        let config = &self.config;
+        let (command, timeout) = match hook {
+            Hook::PreStart => (config.pre_start.clone(), config.hook_timeout),
+            Hook::PostStart => (config.post_start.clone(), config.hook_timeout),
+            Hook::PreStop => (config.pre_stop.clone(), config.hook_timeout),
+            Hook::PostStop => (config.post_stop.clone(), config.hook_timeout),
+            Hook::StopCommand => {
+                let pid = self.processes.get(instance).and_then(Process::pid);
+                let pid = pid.unwrap_or_default().to_string();
+                let command = config.stop_command.as_ref();
+                let wait = config.stop_ladder().first().map_or(config.ttk, |s| s.wait);
+                (command.map(|command| command.replace("{pid}", &pid)), wait)
+            }
+        };
+        let Some(command) = command else {
+            return Ok(None);
+        };
+        info!("{}[{instance}] {hook}: {command}", self.config_name);
+
+        let child = self
+            .shell(&command, instance)
+            .spawn()
+            .map_err(|e| Error::other(format!("{hook} failed: {e}")))?;
+        Ok(Some(RunningHook {
+            hook,
+            child,
+            started_at: Instant::now(),
+            timeout: Duration::from_secs(timeout as u64),
+            exit_status: None,
+        }))
+    }
+
+    /// Moves on the instance if some of its hooks ended, see `hook_ended`.
+    pub(super) fn update_hooks(&mut self, instance: usize) {
+        let hooks = mem::take(&mut self.processes[instance].hooks);
+        for mut running in hooks {
+            match running.result() {
+                None => self.processes[instance].hooks.push(running),
+                Some(result) => self.hook_ended(instance, running.hook, result),
+            }
+        }
+    }
+
+    /// Starts the instance once its `pre_start` succeeded, or counts a failed
+    /// start, and asks it to exit once its `pre_stop` ended. The other hooks
+    /// only have their failure logged, a process outliving its
+    /// `stop_command` is left to its stop ladder.
+    fn hook_ended(&mut self, instance: usize, hook: Hook, result: Result<(), Error>) {
+        let result = result.map_err(|e| Error::other(format!("{hook} failed: {e}")));
+        match (hook, result) {
+            (Hook::PreStart, Ok(())) => self.launch(instance),
+            (Hook::PreStart, Err(e)) => {
+                self.processes[instance].fail_start(&self.config, e)
+            }
+            (Hook::PreStop, result) => {
+                if let Err(e) = result {
+                    warning!("{}[{instance}] {e}", self.config_name);
+                }
+                let process = &self.processes[instance];
+                if process.status == ProcessStatus::Stopping
+                    && process.stop_step.is_none()
+                {
+                    self.ask_to_exit(instance);
+                }
+            }
+            (_, Ok(())) => {}
+            (_, Err(e)) => warning!("{}[{instance}] {e}", self.config_name),
+        }
+    }
+
+    fn shell(&self, command: &str, instance: usize) -> Command {
+        let mut shell = Command::new("sh");
+        shell
+            .arg("-c")
+            .arg(command)
+            .current_dir(&self.config.workdir)
+            .envs(self.command.get_envs().filter_map(|(k, v)| Some((k, v?))))
+            .env("TASKMASTER_PROGRAM", &self.config_name)
+            .env("TASKMASTER_INSTANCE", instance.to_string())
+            .stdin(Stdio::null())
+            .stdout(hook_output(&self.stdout, instance))
+            .stderr(hook_output(&self.stderr, instance));
+        unsafe { shell.pre_exec(unblock_signals) };
+        shell
+    }
+}
+
+fn hook_output(files: &[Option<File>], instance: usize) -> Stdio {
+    match files.get(instance) {
+        Some(Some(file)) => file.try_clone().map_or(Stdio::null(), Stdio::from),
+        Some(None) => Stdio::null(),
+        None => Stdio::inherit(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+    use crate::backend::process::tests::wait_for_exit;
+    use crate::config::{ProgramConfig, Signal};
+
+    fn program(name: &str, config: ProgramConfig) -> Program {
+        Program::build_from((&name.to_string(), &config))
+    }
+
+    fn wait_for_hook(hook: &mut RunningHook) {
+        let mut status = 0;
+        unsafe { libc::waitpid(hook.pid() as libc::pid_t, &mut status, 0) };
+        hook.reaped(ExitStatus::from_raw(status));
+    }
+
+    fn wait_for_hooks(process: &mut Process) {
+        process.hooks.iter_mut().for_each(wait_for_hook);
+    }
+
+    #[test]
+    fn hooks_know_their_program_and_instance() {
+        let output = std::env::temp_dir().join("taskmaster_hook_env");
+        let mut config = ProgramConfig::new();
+        config.command = String::from("true");
+        config.environment_variables = vec![String::from("STAGE=test")];
+        config.pre_start = Some(format!(
+            "echo $STAGE $TASKMASTER_PROGRAM $TASKMASTER_INSTANCE > {}",
+            output.display()
+        ));
+
+        let program = program("web", config);
+        let mut hook = program.start_hook(Hook::PreStart, 2).unwrap().unwrap();
+        wait_for_hook(&mut hook);
+
+        assert!(hook.result().unwrap().is_ok());
+        assert_eq!(fs::read_to_string(&output).unwrap(), "test web 2\n");
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn hooks_running_too_long_fail() {
+        let mut config = ProgramConfig::new();
+        config.pre_stop = Some(String::from("sleep 10"));
+        config.hook_timeout = 5;
+
+        let program = program("slow", config);
+        let mut hook = program.start_hook(Hook::PreStop, 0).unwrap().unwrap();
+        Instant::advance(6);
+        let result = hook.result().unwrap();
+        wait_for_hook(&mut hook);
+
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert_eq!(hook.exit_status.unwrap().signal(), Some(libc::SIGKILL));
+    }
+
+    #[test]
+    fn processes_start_once_their_pre_start_succeeded() {
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.pre_start = Some(String::from("true"));
+        let mut program = program("migrated", config);
+
+        program.update_process_count();
+        assert!(program.processes[0].child.is_err());
+        assert!(program.processes[0].runs_hook(Hook::PreStart));
+        wait_for_hooks(&mut program.processes[0]);
+        program.update_process_status();
+
+        let process = &mut program.processes[0];
+        assert!(process.is_running());
+        process.send_signal(Signal::SIGKILL).unwrap();
+        wait_for_exit(process);
+    }
+
+    #[test]
+    fn processes_are_asked_to_exit_once_their_pre_stop_ended() {
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.pre_stop = Some(String::from("true"));
+        let mut program = program("server", config);
+        program.update_process_count();
+
+        program.stop(0);
+        assert_eq!(program.processes[0].status, ProcessStatus::Stopping);
+        assert!(program.processes[0].stop_step.is_none());
+        wait_for_hooks(&mut program.processes[0]);
+        program.update_process_status();
+        assert!(program.processes[0].stop_step.is_some());
+        wait_for_exit(&mut program.processes[0]);
+        program.update_process_status();
+
+        assert_eq!(program.processes[0].status, ProcessStatus::Stopped);
+    }
+
+    #[test]
+    fn stop_command_is_given_the_pid() {
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.stop_command = Some(String::from("kill -USR1 {pid}"));
+        let mut program = program("server", config);
+        program.update_process_count();
+
+        program.stop(0);
+        assert!(program.processes[0].runs_hook(Hook::StopCommand));
+        wait_for_exit(&mut program.processes[0]);
+        wait_for_hooks(&mut program.processes[0]);
+        program.update_process_status();
+
+        let process = &program.processes[0];
+        assert_eq!(process.status, ProcessStatus::Stopped);
+        assert_eq!(
+            process.last_exit().map(|exit| exit.status),
+            Some(ProcessStatus::Killed(Signal::SIGUSR1))
+        );
+        assert!(process.hooks.is_empty());
+    }
+
+    #[test]
+    fn processes_outliving_their_stop_command_are_killed() {
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.stop_command = Some(String::from("true"));
+        config.ttk = 3;
+        let mut program = program("stubborn", config);
+        program.update_process_count();
+
+        program.stop(0);
+        wait_for_hooks(&mut program.processes[0]);
+        program.update_process_status();
+        assert_eq!(program.processes[0].status, ProcessStatus::Stopping);
+        Instant::advance(4);
+        program.update_process_status();
+        wait_for_exit(&mut program.processes[0]);
+        program.update_process_status();
+
+        let process = &program.processes[0];
+        assert_eq!(process.status, ProcessStatus::Stopped);
+        assert_eq!(
+            process.last_exit().map(|exit| exit.status),
+            Some(ProcessStatus::Killed(Signal::SIGKILL))
+        );
+    }
+
+    #[test]
+    fn failing_pre_start_counts_as_a_failed_start() {
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.retry_start_count = 2;
+        config.pre_start = Some(String::from("exit 1"));
+        let mut program = program("migrate", config);
+
+        program.update_process_count();
+        assert_eq!(program.processes[0].status, ProcessStatus::Starting);
+        assert!(program.processes[0].child.is_err());
+        for _ in 0..3 {
+            wait_for_hooks(&mut program.processes[0]);
+            program.update_process_status();
+        }
+
+        let process = &program.processes[0];
+        assert_eq!(process.try_count, 2);
+        assert_eq!(process.status, ProcessStatus::FailedToStart);
+        assert!(process.hooks.is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+//! Event listeners, programs told about events on their stdin.
+//!
+//! The exchange follows supervisord's eventlisteners:
+//!
+//! 1. the listener writes `READY\n` to its stdout when it can take an event
+//! 2. the daemon writes a header line to its stdin, followed by `len` bytes of
+//!    payload, the event as a JSON object ending with '\n': `ver:1 serial:42
+//!    event:process len:118\n{"event":"process",...}\n`
+//! 3. the listener answers `RESULT 2\nOK` once it handled the event, or `RESULT
+//!    4\nFAIL` to have it sent again later, then goes back to 1.
+//!
+//! Each event goes to one ready instance of a listener program. Events of the
+//! programs and groups in `event_filter` are sent, all of them if it is empty.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read};
+
+use common::Event;
+use logger::{info, warning};
+
+use super::events::MAX_EVENT_BACKLOG;
+use super::{BackEnd, Process, Program};
+
+const PROTOCOL_VERSION: u32 = 1;
+
+/// Where a listener process is in the exchange.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum ListenerState {
+    /// Waiting for READY
+    #[default]
+    Acknowledged,
+    Ready,
+    /// Writing an event, its envelope is not all in the pipe yet
+    Sending(u64, Event),
+    /// Sent an event, waiting for its RESULT
+    Busy(u64, Event),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Ready,
+    Result(bool),
+    Invalid(String),
+}
+
+#[derive(Debug, Default)]
+pub struct Listener {
+    pub state: ListenerState,
+    /// Process the state belongs to, it starts over with every new process
+    pub pid:   Option<u32>,
+    output:    Vec<u8>,
+}
+
+impl Listener {
+    pub fn feed(&mut self, bytes: &[u8]) { self.output.extend_from_slice(bytes); }
+
+    /// Next complete message the listener wrote, None until there is one.
+    pub fn next_message(&mut self) -> Option<Message> {
+        let end = self.output.iter().position(|&b| b == b'\n')?;
+        let line = String::from_utf8_lossy(&self.output[..end]).to_string();
+        let message = match line.trim_end().split_once(' ') {
+            None if line.trim_end() == "READY" => Message::Ready,
+            Some(("RESULT", length)) => {
+                let Ok(length) = length.parse::<usize>() else {
+                    self.output.drain(..=end);
+                    return Some(Message::Invalid(line));
+                };
+                let body = end + 1..end + 1 + length;
+                if self.output.len() < body.end {
+                    return None;
+                }
+                let result = Message::Result(&self.output[body.clone()] == b"OK");
+                self.output.drain(..body.end);
+                return Some(result);
+            }
+            _ => Message::Invalid(line),
+        };
+        self.output.drain(..=end);
+        Some(message)
+    }
+}
+
+/// What is written to a listener for an event.
+pub fn envelope(serial: u64, event: &Event) -> String {
+    let kind = match event {
+        Event::Process { .. } => "process",
+        Event::Reload { .. } => "reload",
+        Event::Shutdown => "shutdown",
+    };
+    let payload = event.encode(true);
+    format!(
+        "ver:{PROTOCOL_VERSION} serial:{serial} event:{kind} len:{}\n{payload}",
+        payload.len()
+    )
+}
+
+fn read_output(process: &mut Process) {
+    let Some(stdout) = process.child.as_mut().ok().and_then(|c| c.stdout.as_mut())
+    else {
+        return;
+    };
+    let mut buf = [0_u8; 4096];
+    loop {
+        match stdout.read(&mut buf) {
+            Ok(0) => return,
+            Ok(bytes) => process.listener.feed(&buf[..bytes]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+            Err(e) => return warning!("listener output: {e}"),
+        }
+    }
+}
+
+impl BackEnd {
+    /// Queues the new events for the listener programs and hands them to the
+    /// instances ready for one.
+    pub fn dispatch_events(&mut self) {
+        let end = self.events.end();
+        let mut queued = vec![];
+        for program in self.programs.values() {
+            if !program.config.event_listener {
+                continue;
+            }
+            let cursor = program.event_cursor.unwrap_or(end);
+            let events: VecDeque<(u64, Event)> = self
+                .events
+                .since(cursor)
+                .filter(|(_, event)| {
+                    self.event_matches(event, &program.config.event_filter)
+                })
+                .map(|(serial, event)| (serial, event.clone()))
+                .collect();
+            queued.push((program.config_name.clone(), events));
+        }
+        for (name, events) in queued {
+            let program = self.programs.get_mut(&name).unwrap();
+            program.event_cursor = Some(end);
+            program.pending_events.extend(events);
+            let excess = program
+                .pending_events
+                .len()
+                .saturating_sub(MAX_EVENT_BACKLOG);
+            if excess > 0 {
+                warning!("{name}: {excess} events dropped, no listener took them");
+                program.pending_events.drain(..excess);
+            }
+            program.serve_listeners();
+        }
+    }
+}
+
+impl Program {
+    fn serve_listeners(&mut self) {
+        for (i, process) in self.processes.iter_mut().enumerate() {
+            // a new process starts the exchange over, what the old one was
+            // handling is sent again
+            if process.listener.pid != process.pid() {
+                if let ListenerState::Sending(serial, event)
+                | ListenerState::Busy(serial, event) = &process.listener.state
+                {
+                    self.pending_events.push_front((*serial, event.clone()));
+                }
+                process.listener = Listener {
+                    pid: process.pid(),
+                    ..Default::default()
+                };
+            }
+            // an event is only handed over once all of its envelope was
+            // written, the rest of it is never sent again
+            if let ListenerState::Sending(serial, event) = &process.listener.state {
+                if process.pending_input.is_empty() {
+                    process.listener.state =
+                        ListenerState::Busy(*serial, event.clone());
+                }
+            }
+            read_output(process);
+            while let Some(message) = process.listener.next_message() {
+                let state = std::mem::take(&mut process.listener.state);
+                process.listener.state = match (message, state) {
+                    (Message::Ready, ListenerState::Busy(serial, event)) => {
+                        warning!("{}[{i}] ready before answering", self.config_name);
+                        self.pending_events.push_front((serial, event));
+                        ListenerState::Ready
+                    }
+                    (Message::Ready, _) => ListenerState::Ready,
+                    (Message::Result(ok), ListenerState::Busy(serial, event)) => {
+                        if !ok {
+                            info!("{}[{i}] failed event {serial}", self.config_name);
+                            self.pending_events.push_back((serial, event));
+                        }
+                        ListenerState::Acknowledged
+                    }
+                    (message, state) => {
+                        warning!("{}[{i}] unexpected {message:?}", self.config_name);
+                        state
+                    }
+                };
+            }
+            if process.listener.state != ListenerState::Ready {
+                continue;
+            }
+            let Some((serial, event)) = self.pending_events.pop_front() else {
+                continue;
+            };
+            match process.write_stdin(envelope(serial, &event).as_bytes()) {
+                Ok(()) if process.pending_input.is_empty() => {
+                    process.listener.state = ListenerState::Busy(serial, event)
+                }
+                Ok(()) => {
+                    process.listener.state = ListenerState::Sending(serial, event)
+                }
+                Err(e) => {
+                    warning!("{}[{i}] event {serial} not sent: {e}", self.config_name);
+                    self.pending_events.push_front((serial, event));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::process::tests::wait_for_exit;
+    use crate::config::{ProgramConfig, Signal};
+
+    #[test]
+    fn listener_messages_are_read_once_complete() {
+        let mut listener = Listener::default();
+
+        listener.feed(b"READY\nRESULT 2\nO");
+        assert_eq!(listener.next_message(), Some(Message::Ready));
+        assert_eq!(listener.next_message(), None);
+        listener.feed(b"KRESULT 4\nFAIL");
+        assert_eq!(listener.next_message(), Some(Message::Result(true)));
+        assert_eq!(listener.next_message(), Some(Message::Result(false)));
+        listener.feed(b"hello\n");
+        assert_eq!(listener.next_message(), Some(Message::Invalid("hello".into())));
+    }
+
+    #[test]
+    fn envelope_announces_the_payload_length() {
+        let envelope = envelope(7, &Event::Shutdown);
+        let (header, payload) = envelope.split_once('\n').unwrap();
+
+        assert_eq!(
+            header,
+            format!("ver:1 serial:7 event:shutdown len:{}", payload.len())
+        );
+        assert_eq!(payload, "{\"event\":\"shutdown\"}\n");
+    }
+
+    #[test]
+    fn events_partly_written_are_finished_and_not_sent_again() {
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.event_listener = true;
+        let mut program = Program::build_from((&String::from("listener"), &config));
+        program.update_process_count();
+        let process = &mut program.processes[0];
+        // a listener that reads nothing, with a full pipe
+        process.write_stdin(&[b'\n'; 65536]).unwrap();
+        process.listener = Listener {
+            state: ListenerState::Ready,
+            pid: process.pid(),
+            ..Default::default()
+        };
+        program.pending_events.push_back((7, Event::Shutdown));
+
+        program.serve_listeners();
+        program.serve_listeners();
+
+        let process = &mut program.processes[0];
+        assert_eq!(process.listener.state, ListenerState::Sending(7, Event::Shutdown));
+        assert!(program.pending_events.is_empty());
+        process.send_signal(Signal::SIGKILL).unwrap();
+        wait_for_exit(process);
+    }
+}
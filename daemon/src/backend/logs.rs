@@ -0,0 +1,247 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+
+use common::CmdError;
+
+use super::Target;
+
+const DFL_TAIL_LINES: usize = 10;
+const BLOCK_SIZE: u64 = 4096;
+/// Largest range a single READ may return.
+pub const MAX_READ_LENGTH: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl FromStr for Stream {
+    type Err = CmdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stdout" => Ok(Stream::Stdout),
+            "stderr" => Ok(Stream::Stderr),
+            other => Err(format!("{other} is not a stream (stdout|stderr)").into()),
+        }
+    }
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// Arguments of `log <prog>[:instance] [stdout|stderr] [-n N] [-f]`.
+#[derive(Debug, PartialEq)]
+pub struct LogQuery {
+    pub target: Target,
+    pub stream: Stream,
+    pub lines:  usize,
+    pub follow: bool,
+}
+
+impl LogQuery {
+    pub fn parse(arguments: &[String]) -> Result<LogQuery, CmdError> {
+        let mut arguments = arguments.iter();
+        let target: Target = match arguments.next() {
+            Some(target) => target.parse()?,
+            None => return Err("missing program name".into()),
+        };
+        let mut query = LogQuery {
+            target,
+            stream: Stream::Stdout,
+            lines: DFL_TAIL_LINES,
+            follow: false,
+        };
+        while let Some(argument) = arguments.next() {
+            match argument.as_str() {
+                "-f" => query.follow = true,
+                "-n" => query.lines = parse_count(arguments.next())?,
+                stream => query.stream = stream.parse()?,
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// Arguments of `read <prog>[:instance] <stream> <offset> <length>`.
+#[derive(Debug, PartialEq)]
+pub struct ReadQuery {
+    pub target: Target,
+    pub stream: Stream,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl ReadQuery {
+    pub fn parse(arguments: &[String]) -> Result<ReadQuery, CmdError> {
+        let [target, stream, offset, length] = arguments else {
+            return Err("READ requires <prog> <stream> <offset> <length>".into());
+        };
+        let (Ok(offset), Ok(length)) = (offset.parse(), length.parse()) else {
+            return Err("offset and length must be positive numbers".into());
+        };
+        if length > MAX_READ_LENGTH {
+            return Err(format!("length is limited to {MAX_READ_LENGTH} bytes").into());
+        }
+        Ok(ReadQuery {
+            target: target.parse()?,
+            stream: stream.parse()?,
+            offset,
+            length,
+        })
+    }
+}
+
+pub fn parse_count(argument: Option<&String>) -> Result<usize, CmdError> {
+    match argument.map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) => Ok(n),
+        _ => Err("-n requires a number of lines".into()),
+    }
+}
+
+/// Last `lines` lines of the file and the offset its end was at.
+pub fn tail(path: &Path, lines: usize) -> io::Result<(String, u64)> {
+    let mut file = File::open(path)?;
+    let end = file.seek(SeekFrom::End(0))?;
+    if lines == 0 {
+        return Ok((String::new(), end));
+    }
+    let mut start = end;
+    let mut buf = vec![];
+    // a trailing newline terminates the last line, it does not start a new one
+    let mut newlines = 0;
+    while start > 0 && newlines <= lines {
+        let block = BLOCK_SIZE.min(start);
+        start -= block;
+        let mut chunk = vec![0; block as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.append(&mut buf);
+        buf = chunk;
+        newlines = buf.iter().filter(|&&byte| byte == b'\n').count();
+        if buf.ends_with(b"\n") {
+            newlines -= 1;
+        }
+    }
+    let skip = (newlines + 1).saturating_sub(lines);
+    let offset = match skip {
+        0 => 0,
+        _ => buf
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte == b'\n')
+            .nth(skip - 1)
+            .map_or(0, |(i, _)| i + 1),
+    };
+    Ok((String::from_utf8_lossy(&buf[offset..]).into(), end))
+}
+
+/// First `lines` lines of the file.
+pub fn head(path: &Path, lines: usize) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = vec![];
+    for _ in 0..lines {
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&buf).into())
+}
+
+/// At most `length` bytes starting at `offset`, fewer once the end is reached.
+pub fn read_range(path: &Path, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![];
+    file.take(length).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Everything written after `offset`, starting over if the file was
+/// truncated in the meantime.
+pub fn read_from(path: &Path, offset: u64) -> io::Result<(String, u64)> {
+    let mut file = File::open(path)?;
+    let end = file.seek(SeekFrom::End(0))?;
+    let offset = if end < offset { 0 } else { offset };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![];
+    file.read_to_end(&mut buf)?;
+    Ok((String::from_utf8_lossy(&buf).into(), offset + buf.len() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn log_file(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("taskmaster_{name}"));
+        File::create(&path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn tail_should_return_the_last_lines() {
+        let path = log_file("tail", "one\ntwo\nthree\nfour\n");
+        assert_eq!(tail(&path, 2).unwrap(), ("three\nfour\n".into(), 19));
+        assert_eq!(tail(&path, 10).unwrap().0, "one\ntwo\nthree\nfour\n");
+        assert_eq!(tail(&path, 0).unwrap().0, "");
+    }
+
+    #[test]
+    fn tail_should_keep_an_unterminated_last_line() {
+        let path = log_file("tail_partial", "one\ntwo\nthr");
+        assert_eq!(tail(&path, 1).unwrap().0, "thr");
+        assert_eq!(tail(&path, 2).unwrap().0, "two\nthr");
+    }
+
+    #[test]
+    fn read_from_should_return_what_was_appended() {
+        let path = log_file("read_from", "one\ntwo\n");
+        assert_eq!(read_from(&path, 4).unwrap(), ("two\n".into(), 8));
+        assert_eq!(read_from(&path, 8).unwrap(), ("".into(), 8));
+        assert_eq!(read_from(&path, 100).unwrap(), ("one\ntwo\n".into(), 8));
+    }
+
+    #[test]
+    fn head_should_return_the_first_lines() {
+        let path = log_file("head", "one\ntwo\nthree");
+        assert_eq!(head(&path, 2).unwrap(), "one\ntwo\n");
+        assert_eq!(head(&path, 5).unwrap(), "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn read_range_should_stop_at_the_end_of_the_file() {
+        let path = log_file("read_range", "0123456789");
+        assert_eq!(read_range(&path, 2, 3).unwrap(), b"234");
+        assert_eq!(read_range(&path, 8, 5).unwrap(), b"89");
+        assert_eq!(read_range(&path, 20, 5).unwrap(), b"");
+    }
+
+    #[test]
+    fn log_query_should_parse_every_option() {
+        let arguments: Vec<String> = ["web:1", "stderr", "-n", "5", "-f"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        let query = LogQuery::parse(&arguments).unwrap();
+        assert_eq!(query.target.to_string(), "web:1");
+        assert_eq!(query.stream, Stream::Stderr);
+        assert_eq!(query.lines, 5);
+        assert!(query.follow);
+    }
+}
@@ -0,0 +1,116 @@
+//! Readiness notifications, as systemd's sd_notify: a process with `ready:
+//! notify` finds the path of a datagram socket in NOTIFY_SOCKET and sends
+//! it newline separated assignments.
+//!
+//! - `READY=1` it started, it goes from Starting to Active
+//! - `STATUS=...` a line shown in its status
+//! - `WATCHDOG=1` it is alive, it is aborted if none comes for `watchdog`
+//!   seconds, `WATCHDOG=trigger` aborts it at once
+//! - `STOPPING=1` it is exiting on its own, it is not started again
+//!
+//! Other assignments are ignored. Each instance has its own socket, kept
+//! across its restarts.
+
+use std::io::ErrorKind;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, process};
+
+#[derive(Debug, PartialEq)]
+pub enum Notification {
+    Ready,
+    Status(String),
+    Watchdog,
+    WatchdogTrigger,
+    Stopping,
+}
+
+impl Notification {
+    /// The notifications of a datagram, in the order they were written.
+    pub fn parse(datagram: &str) -> Vec<Notification> {
+        datagram
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .filter_map(|assignment| match assignment {
+                ("READY", "1") => Some(Notification::Ready),
+                ("STATUS", status) => Some(Notification::Status(status.to_string())),
+                ("WATCHDOG", "1") => Some(Notification::Watchdog),
+                ("WATCHDOG", "trigger") => Some(Notification::WatchdogTrigger),
+                ("STOPPING", "1") => Some(Notification::Stopping),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub struct NotifySocket {
+    socket: UnixDatagram,
+    path:   PathBuf,
+}
+
+impl NotifySocket {
+    /// Binds the socket of an instance, replacing one a previous daemon may
+    /// have left.
+    pub fn bind(program: &str, instance: usize) -> io::Result<NotifySocket> {
+        let path = env::temp_dir()
+            .join(format!("taskmaster-{}-{program}-{instance}.notify", process::id()));
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        Ok(NotifySocket { socket, path })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Everything sent since last time, without blocking.
+    pub fn read(&self) -> io::Result<Vec<Notification>> {
+        let mut notifications = vec![];
+        let mut buf = [0_u8; 4096];
+        loop {
+            match self.socket.recv(&mut buf) {
+                Ok(bytes) => notifications.extend(Notification::parse(
+                    &String::from_utf8_lossy(&buf[..bytes]),
+                )),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    return Ok(notifications)
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) { fs::remove_file(&self.path).unwrap_or_default(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_are_read_in_order() {
+        let socket = NotifySocket::bind("notify_test", 0).unwrap();
+        let client = UnixDatagram::unbound().unwrap();
+        client
+            .send_to(b"STATUS=loading\nMAINPID=42", socket.path())
+            .unwrap();
+        client
+            .send_to(b"READY=1\nSTATUS=serving\n", socket.path())
+            .unwrap();
+
+        assert_eq!(
+            socket.read().unwrap(),
+            [
+                Notification::Status("loading".into()),
+                Notification::Ready,
+                Notification::Status("serving".into()),
+            ]
+        );
+        assert!(socket.read().unwrap().is_empty());
+    }
+}
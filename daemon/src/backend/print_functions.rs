@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use logger::debug;
 
@@ -15,7 +16,7 @@ pub fn print_programs(msg: &str, programs: &HashMap<String, ProgramConfig>) {
 
 pub fn print_processes(programs: &HashMap<String, Program>) -> String {
     let mut dump = String::from("Process dump\n");
-    for (_, program) in programs.iter() {
+    for program in programs.values() {
         for (i, process) in program.processes.iter().enumerate() {
             dump.push_str(&format!("{:15}[{}]: {}\n", program.config_name, i, process));
         }
@@ -23,3 +24,33 @@ pub fn print_processes(programs: &HashMap<String, Program>) -> String {
     dump.pop();
     dump
 }
+
+/// Local time as `YYYY-MM-DD HH:MM:SS`.
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as libc::time_t)
+        .unwrap_or(0);
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let mut buf = [0u8; 32];
+    let len = unsafe {
+        libc::localtime_r(&secs, &mut tm);
+        libc::strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            c"%Y-%m-%d %H:%M:%S".as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buf[..len]).into()
+}
+
+/// Elapsed seconds as `[Nd ]HH:MM:SS`.
+pub fn format_duration(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let clock = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    match days {
+        0 => clock,
+        _ => format!("{days}d {clock}"),
+    }
+}
@@ -1,7 +1,9 @@
 mod class;
 mod status;
+mod terminal;
 #[cfg(test)]
-mod tests;
+pub(super) mod tests;
 
 pub use class::Process;
-pub use status::ProcessStatus;
+pub use status::{ExitRecord, ProcessStatus, StopStep, Transition};
+pub use terminal::{acquire_controlling_terminal, Terminal};
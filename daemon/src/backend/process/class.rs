@@ -1,23 +1,68 @@
-use std::io::{self, Error};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Write};
+use std::os::fd::AsRawFd;
 use std::os::unix::process::ExitStatusExt;
-use std::process::{Child, Command};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::SystemTime;
 #[cfg(not(test))]
 use std::time::{Duration, Instant};
 
-use logger::{error, info};
+use common::{syscall, ProcessState};
+use logger::{error, info, warning};
 
 #[cfg(test)]
 use super::tests::{Duration, Instant};
-use super::ProcessStatus;
+use super::{ExitRecord, ProcessStatus, StopStep, Terminal, Transition};
+use crate::backend::hooks::RunningHook;
+use crate::backend::listener::Listener;
+use crate::backend::notify::Notification;
+use crate::backend::readiness::PortProbe;
 use crate::config::{ProgramConfig, RestartOption, Signal};
 
+/// Exits remembered per process, older ones are forgotten.
+const MAX_EXIT_HISTORY: usize = 10;
+/// Input kept for a process that does not read it, more is refused.
+const MAX_PENDING_INPUT: usize = 64 * 1024;
+
 pub struct Process {
     pub child:            Result<Child, Error>,
+    /// How the child exited, once the daemon reaped it and until handled
+    pub exit_status:      Option<ExitStatus>,
     pub status:           ProcessStatus,
     pub try_count:        u32,
+    pub restart_count:    u32,
     pub started_at:       Option<Instant>,
+    /// When the current step of the stop ladder was taken
+    pub stop_requested:   Option<Instant>,
+    pub stop_step:        Option<StopStep>,
+    pub start_time:       Option<SystemTime>,
+    pub exits:            VecDeque<ExitRecord>,
+    pub transitions:      Vec<Transition>,
     pub should_try_again: bool,
     pub should_restart:   bool,
+    pub tty:              bool,
+    pub terminal:         Option<Terminal>,
+    /// Input accepted for the process, not yet written to it
+    pub pending_input:    Vec<u8>,
+    pub listener:         Listener,
+    pub notified:         Notified,
+    /// Hooks of the instance still running, reaped with the processes
+    pub hooks:            Vec<RunningHook>,
+}
+
+/// What a process waited on to be ready told or showed since it started.
+#[derive(Debug, Default)]
+pub struct Notified {
+    /// It sent READY=1 or met its `ready_when`
+    pub ready:         bool,
+    /// Where its output starts in the stdout file, for `stdout_matches`
+    pub output_offset: u64,
+    timed_out:         bool,
+    pub status:        Option<String>,
+    /// When it last said it was alive, from when it was ready
+    pub heartbeat:     Option<Instant>,
+    /// A connection to its `port_open` port in progress
+    pub port_probe:    Option<PortProbe>,
 }
 
 impl Process {
@@ -26,13 +71,17 @@ impl Process {
         initial_status: ProcessStatus,
     ) -> Process {
         let started_at = child_result.is_ok().then_some(Instant::now()).or(None);
+        let start_time = child_result.is_ok().then_some(SystemTime::now());
 
-        Process {
+        let mut process = Process {
             child: child_result,
             status: initial_status,
             started_at,
+            start_time,
             ..Process::default()
-        }
+        };
+        process.record(initial_status.into());
+        process
     }
 
     fn spawn_process(command: &mut Command) -> Result<Child, Error> {
@@ -40,11 +89,96 @@ impl Process {
             return Err(Error::other("Empty command"));
         }
 
-        command.spawn()
+        let child = command.spawn()?;
+        // the daemon must never block on a child that stops reading its input
+        // or has nothing to say
+        let stdin = child.stdin.as_ref().map(|stdin| stdin.as_raw_fd());
+        let stdout = child.stdout.as_ref().map(|stdout| stdout.as_raw_fd());
+        for fd in stdin.into_iter().chain(stdout) {
+            let flags = syscall!(fcntl(fd, libc::F_GETFL))?;
+            syscall!(fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+        }
+        Ok(child)
     }
 
-    pub fn start(command: &mut Command) -> Process {
+    fn spawn(&mut self, command: &mut Command) -> Result<Child, Error> {
+        if !self.tty {
+            return Process::spawn_process(command);
+        }
+        let (terminal, slave) = Terminal::open()?;
+        command
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave);
         let child = Process::spawn_process(command);
+        // only the child may keep the slave open, or the hangup is never seen
+        command
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        self.terminal = Some(terminal);
+        child
+    }
+
+    /// Queues input for the process and writes what its stdin takes without
+    /// blocking, the rest is written by `flush_stdin` as it reads.
+    pub fn write_stdin(&mut self, input: &[u8]) -> Result<(), Error> {
+        if self.terminal.is_none() {
+            let child = self
+                .child
+                .as_ref()
+                .map_err(|e| Error::other(e.to_string()))?;
+            if child.stdin.is_none() {
+                return Err(Error::other("stdin is not a pipe"));
+            }
+        }
+        if self.pending_input.len() + input.len() > MAX_PENDING_INPUT {
+            return Err(Error::other("process is not reading its input"));
+        }
+        self.pending_input.extend_from_slice(input);
+        self.flush_stdin()
+    }
+
+    /// Writes the pending input the stdin of the process takes, dropping it
+    /// all if the process can no longer be written to.
+    pub fn flush_stdin(&mut self) -> Result<(), Error> {
+        while !self.pending_input.is_empty() {
+            let stdin = self.child.as_ref().ok().and_then(|c| c.stdin.as_ref());
+            let written = match (&self.terminal, stdin) {
+                (Some(terminal), _) => (&terminal.master).write(&self.pending_input),
+                (None, Some(mut stdin)) => stdin.write(&self.pending_input),
+                (None, None) => Err(Error::other("stdin is not a pipe")),
+            };
+            match written {
+                Ok(0) => break,
+                Ok(bytes) => {
+                    self.pending_input.drain(..bytes);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    self.pending_input.clear();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn start(command: &mut Command) -> Process {
+        Process::start_with(command, false)
+    }
+
+    pub fn start_on_terminal(command: &mut Command) -> Process {
+        Process::start_with(command, true)
+    }
+
+    fn start_with(command: &mut Command, tty: bool) -> Process {
+        let mut process = Process {
+            tty,
+            ..Process::default()
+        };
+        let child = process.spawn(command);
 
         let status = if child
             .as_ref()
@@ -55,12 +189,19 @@ impl Process {
             ProcessStatus::Starting
         };
 
-        Process::new(child, status)
+        Process {
+            tty,
+            terminal: process.terminal.take(),
+            ..Process::new(child, status)
+        }
     }
 
     pub fn update_status(&mut self, config: &ProgramConfig) {
         if self.child.is_err() {
-            self.status = ProcessStatus::FailedToStart;
+            // a start that failed before spawning waits for its next try
+            if !self.should_try_again {
+                self.set_status(ProcessStatus::FailedToStart);
+            }
             return;
         }
 
@@ -71,6 +212,8 @@ impl Process {
             ProcessStatus::GracefulExit(_) => self.handle_graceful_exit_phase(config),
             ProcessStatus::Killed(_) => self.handle_killed_phase(config),
             ProcessStatus::FailedExit(_) => self.handle_failed_exit_phase(config),
+            ProcessStatus::Stopping => self.handle_stopping_phase(config),
+            ProcessStatus::Stopped => {}
         }
 
         // self.update_status_match(config);
@@ -83,8 +226,13 @@ impl Process {
         }
 
         info!("Restarting process {:?}", command.get_program());
-        self.child = Process::spawn_process(command);
-        self.status = ProcessStatus::Starting;
+        self.notified = Notified::default();
+        self.pending_input.clear();
+        self.child = self.spawn(command);
+        self.set_status(ProcessStatus::Starting);
+        self.restart_count += 1;
+        self.started_at = Some(Instant::now());
+        self.start_time = Some(SystemTime::now());
     }
 
     pub fn try_start_again(&mut self, command: &mut Command) {
@@ -99,86 +247,266 @@ impl Process {
             command.get_program(),
         );
 
-        self.child = Process::spawn_process(command);
+        self.record(ProcessState::Backoff {
+            retry: self.try_count,
+        });
+        self.notified = Notified::default();
+        self.pending_input.clear();
+        self.child = self.spawn(command);
         self.started_at = Some(Instant::now());
+        self.start_time = Some(SystemTime::now());
+        self.record(ProcessState::Starting);
+    }
+
+    /// Counts a start that failed before anything was spawned like a process
+    /// dying while starting, another try is made while retries are left.
+    pub fn fail_start(&mut self, config: &ProgramConfig, error: Error) {
+        error!("Failed to start process: {error}");
+        self.should_restart = false;
+        if std::mem::take(&mut self.should_try_again) {
+            self.try_count += 1;
+        }
+        self.child = Err(error);
+        self.started_at = None;
+        self.start_time = None;
+        self.set_status(ProcessStatus::Starting);
+        if self.try_count >= config.retry_start_count {
+            self.exited(ProcessStatus::FailedToStart);
+        } else {
+            self.should_try_again = true;
+        }
+    }
+
+    /// Asks the process to exit with the first step of the stop ladder, the
+    /// next steps are taken while it does not, until it is killed. The
+    /// `stop_command` is left to the caller, it replaces the first signal.
+    /// One that was never spawned is stopped right away.
+    pub fn stop(&mut self, config: &ProgramConfig) -> Result<(), Error> {
+        self.should_restart = false;
+        self.should_try_again = false;
+        if self.pid().is_none() {
+            self.set_status(ProcessStatus::Stopped);
+            return Ok(());
+        }
+        self.set_status(ProcessStatus::Stopping);
+        self.take_stop_step(0, config)
+    }
+
+    /// Kills the process right away, as the last step of its stop ladder.
+    pub fn kill(&mut self, config: &ProgramConfig) -> Result<(), Error> {
+        self.should_restart = false;
+        self.should_try_again = false;
+        self.set_status(ProcessStatus::Stopping);
+        self.take_stop_step(config.stop_ladder().len(), config)
+    }
+
+    /// Marks the process as stopping without signaling it, while its
+    /// `pre_stop` hook runs or once it said it is exiting on its own.
+    pub fn hold_stop(&mut self) {
+        self.should_restart = false;
+        self.should_try_again = false;
+        self.set_status(ProcessStatus::Stopping);
+        self.stop_step = None;
+        self.stop_requested = None;
+    }
+
+    /// Gives up a start still waiting for its `pre_start` hook.
+    pub fn cancel_start(&mut self) {
+        self.should_restart = false;
+        self.should_try_again = false;
+        self.set_status(ProcessStatus::Stopped);
+    }
+
+    fn take_stop_step(
+        &mut self,
+        index: usize,
+        config: &ProgramConfig,
+    ) -> Result<(), Error> {
+        let signal = match config.stop_ladder().get(index) {
+            None => Some(Signal::SIGKILL),
+            Some(_) if index == 0 && config.stop_command.is_some() => None,
+            Some(step) => Some(step.signal),
+        };
+        self.stop_step = Some(StopStep { index, signal });
+        self.stop_requested = Some(Instant::now());
+        match signal {
+            Some(signal) => self.send_signal(signal),
+            None => Ok(()),
+        }
+    }
+
+    /// Signals the process, refused once it exited as its pid may be reused.
+    pub fn send_signal(&self, signal: Signal) -> Result<(), Error> {
+        let running = self.is_running() && self.exit_status.is_none();
+        let Some(pid) = self.pid().filter(|_| running) else {
+            return Err(Error::other("not running"));
+        };
+        syscall!(kill(pid as libc::pid_t, signal.into()))?;
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.child.is_ok()
+            && matches!(
+                self.status,
+                ProcessStatus::Starting
+                    | ProcessStatus::Active
+                    | ProcessStatus::Stopping
+            )
+    }
+
+    /// Hands the process the exit status the daemon reaped for its pid.
+    pub fn reaped(&mut self, status: ExitStatus) { self.exit_status = Some(status); }
+
+    fn handle_stopping_phase(&mut self, config: &ProgramConfig) {
+        match self.exit_status.take() {
+            Some(status) => {
+                self.exited(exit_status(status, config));
+                self.set_status(ProcessStatus::Stopped);
+            }
+            None => {
+                let Some(step) = self.stop_step else {
+                    return;
+                };
+                // killed, there is nothing left to wait for
+                let Some(wait) = config.stop_ladder().get(step.index).map(|s| s.wait)
+                else {
+                    return;
+                };
+                let wait = Duration::from_secs(wait as u64);
+                if self
+                    .stop_requested
+                    .as_ref()
+                    .is_some_and(|t| t.elapsed() >= wait)
+                {
+                    warning!("{} still alive after {step}", self.pid().unwrap_or(0));
+                    self.take_stop_step(step.index + 1, config)
+                        .unwrap_or_else(|e| error!("{e}"));
+                }
+            }
+        }
     }
 
     fn handle_starting_phase(&mut self, config: &ProgramConfig) {
         if self.try_count >= config.retry_start_count {
-            self.status = ProcessStatus::FailedToStart;
+            self.exited(ProcessStatus::FailedToStart);
         } else {
             let time_elapsed = self.time_elapsed();
 
             let expected_duration =
                 Duration::from_secs(config.succesful_start_after as u64);
 
-            let child = self.child.as_mut().unwrap();
-            if is_alive(child) {
-                if time_elapsed > expected_duration {
-                    self.status = ProcessStatus::Active;
+            // one waited on is only started once ready, its exit is a failed
+            // start
+            let (started, ran_long_enough) = match config.waits_for_ready() {
+                false => (
+                    time_elapsed > expected_duration,
+                    time_elapsed >= expected_duration,
+                ),
+                true => (self.notified.ready, false),
+            };
+            let timeout = Duration::from_secs(config.ready_timeout() as u64);
+            if config.waits_for_ready()
+                && config.ready_timeout() > 0
+                && !started
+                && time_elapsed >= timeout
+                && !self.notified.timed_out
+            {
+                warning!(
+                    "{} not ready after {}s",
+                    self.pid().unwrap_or(0),
+                    config.ready_timeout()
+                );
+                self.notified.timed_out = true;
+                self.send_signal(Signal::SIGKILL)
+                    .unwrap_or_else(|e| error!("{e}"));
+            }
+            match self.exit_status.take() {
+                None => {
+                    if started {
+                        self.set_status(ProcessStatus::Active);
+                    }
                 }
-            } else {
-                match child.wait() {
-                    Ok(status) => {
-                        if time_elapsed >= expected_duration {
-                            if let Some(code) = status.code() {
-                                if config.success_codes.contains(&(code as u32)) {
-                                    self.status =
-                                        ProcessStatus::GracefulExit(code as u32);
-                                    self.handle_graceful_exit_phase(config);
-                                } else {
-                                    self.status =
-                                        ProcessStatus::FailedExit(code as u32);
-                                    self.handle_failed_exit_phase(config);
-                                }
-                            } else if let Some(signal) = status.signal() {
-                                self.status =
-                                    ProcessStatus::Killed(Signal::from(signal));
-                                self.handle_killed_phase(config)
+                Some(status) => {
+                    if ran_long_enough {
+                        if let Some(code) = status.code() {
+                            if config.success_codes.contains(&(code as u32)) {
+                                self.exited(ProcessStatus::GracefulExit(code as u32));
+                                self.handle_graceful_exit_phase(config);
                             } else {
-                                error!(
-                                    "Error evaluating process status: : {}",
-                                    config.command
-                                );
+                                self.exited(ProcessStatus::FailedExit(code as u32));
+                                self.handle_failed_exit_phase(config);
                             }
+                        } else if let Some(signal) = status.signal() {
+                            self.exited(killed(signal));
+                            self.handle_killed_phase(config)
                         } else {
-                            self.should_try_again = true;
+                            error!(
+                                "Error evaluating process status: : {}",
+                                config.command
+                            );
                         }
+                    } else {
+                        self.should_try_again = true;
                     }
-                    Err(e) => todo!("failed waiting for process to finish {e}"),
                 }
             }
         }
     }
 
     fn handle_active_phase(&mut self, config: &ProgramConfig) {
-        let child = self
-            .child
-            .as_mut()
-            .expect("Process was active, but there was no child!!");
-        match child.try_wait() {
-            Ok(None) => {} // it's still alive
-            Ok(Some(status)) => {
-                if let Some(code) = status.code() {
-                    if config.success_codes.contains(&(code as u32)) {
-                        self.status = ProcessStatus::GracefulExit(code as u32);
-                    } else {
-                        self.status = ProcessStatus::FailedExit(code as u32);
-                    }
-                } else {
-                    let signal = status.signal().unwrap();
-                    self.status = ProcessStatus::Killed(Signal::from(signal));
-                }
+        // still alive until reaped
+        if let Some(status) = self.exit_status.take() {
+            self.exited(exit_status(status, config));
+            return;
+        }
+        let watchdog = Duration::from_secs(config.watchdog as u64);
+        let missed = self
+            .notified
+            .heartbeat
+            .as_ref()
+            .is_some_and(|heartbeat| heartbeat.elapsed() >= watchdog);
+        if config.watchdog > 0 && missed {
+            warning!("{} missed its watchdog", self.pid().unwrap_or(0));
+            self.abort();
+        }
+    }
+
+    /// Handles what the process sent to its notify socket.
+    pub fn notify(&mut self, notification: Notification) {
+        match notification {
+            Notification::Ready => {
+                self.notified.ready = true;
+                self.notified.heartbeat = Some(Instant::now());
             }
-            Err(err) => {
-                error!(
-                    "Error attempting to wait for child [{}]: {}",
-                    config.command, err
-                );
+            Notification::Status(status) => self.notified.status = Some(status),
+            Notification::Watchdog => self.notified.heartbeat = Some(Instant::now()),
+            Notification::WatchdogTrigger => {
+                warning!("{} triggered its watchdog", self.pid().unwrap_or(0));
+                self.abort();
+            }
+            // exiting on purpose, it is waited for without being signaled
+            // until the daemon asks it to stop
+            Notification::Stopping
+                if matches!(
+                    self.status,
+                    ProcessStatus::Starting | ProcessStatus::Active
+                ) =>
+            {
+                self.hold_stop();
             }
+            Notification::Stopping => {}
         }
     }
 
+    /// Aborts a process that stopped answering, as systemd does, it is
+    /// started again as any killed process.
+    fn abort(&mut self) {
+        self.notified.heartbeat = None;
+        self.send_signal(Signal::SIGABRT)
+            .unwrap_or_else(|e| error!("{e}"));
+    }
+
     fn handle_graceful_exit_phase(&mut self, config: &ProgramConfig) {
         match config.restart {
             RestartOption::ALWAYS => {
@@ -207,6 +535,48 @@ impl Process {
         }
     }
 
+    fn set_status(&mut self, status: ProcessStatus) {
+        if self.status != status {
+            self.status = status;
+            self.record(status.into());
+        }
+    }
+
+    fn record(&mut self, state: ProcessState) {
+        self.transitions.push(Transition {
+            state,
+            pid: self.pid(),
+            at: SystemTime::now(),
+        });
+    }
+
+    /// Moves to a status the process ended in, keeping it in the history.
+    fn exited(&mut self, status: ProcessStatus) {
+        self.set_status(status);
+        self.exits.push_back(ExitRecord {
+            status,
+            at: SystemTime::now(),
+            stop_step: self.stop_step.take(),
+        });
+        if self.exits.len() > MAX_EXIT_HISTORY {
+            self.exits.pop_front();
+        }
+    }
+
+    pub fn pid(&self) -> Option<u32> { self.child.as_ref().ok().map(Child::id) }
+
+    /// Seconds since the current run started, None unless running.
+    pub fn uptime(&self) -> Option<u64> {
+        match self.status {
+            ProcessStatus::Starting | ProcessStatus::Active => {
+                self.started_at.as_ref().map(|t| t.elapsed().as_secs())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn last_exit(&self) -> Option<&ExitRecord> { self.exits.back() }
+
     fn time_elapsed(&mut self) -> Duration {
         self.started_at.get_or_insert(Instant::now()).elapsed()
     }
@@ -215,15 +585,25 @@ impl Process {
 impl Default for Process {
     fn default() -> Self {
         Process {
-            child:            Err(Error::new(
-                io::ErrorKind::Other,
-                "Unititialized process",
-            )),
+            child:            Err(Error::other("Unititialized process")),
+            exit_status:      None,
             status:           ProcessStatus::FailedToStart,
             try_count:        0,
+            restart_count:    0,
             started_at:       None,
+            stop_requested:   None,
+            stop_step:        None,
+            start_time:       None,
+            exits:            VecDeque::new(),
+            transitions:      vec![],
+            listener:         Listener::default(),
             should_restart:   false,
             should_try_again: false,
+            tty:              false,
+            terminal:         None,
+            pending_input:    vec![],
+            notified:         Notified::default(),
+            hooks:            vec![],
         }
     }
 }
@@ -247,4 +627,21 @@ impl std::fmt::Display for Process {
     }
 }
 
-fn is_alive(child: &mut Child) -> bool { matches!(child.try_wait(), Ok(None)) }
+fn exit_status(status: ExitStatus, config: &ProgramConfig) -> ProcessStatus {
+    match (status.code(), status.signal()) {
+        (Some(code), _) if config.success_codes.contains(&(code as u32)) => {
+            ProcessStatus::GracefulExit(code as u32)
+        }
+        (Some(code), _) => ProcessStatus::FailedExit(code as u32),
+        (None, signal) => killed(signal.unwrap_or_default()),
+    }
+}
+
+/// How a process killed by `signal` ended, reported like a shell would if the
+/// signal is not one we know.
+fn killed(signal: i32) -> ProcessStatus {
+    match Signal::try_from(signal) {
+        Ok(signal) => ProcessStatus::Killed(signal),
+        Err(_) => ProcessStatus::FailedExit(128 + signal as u32),
+    }
+}
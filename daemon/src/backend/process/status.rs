@@ -1,3 +1,7 @@
+use std::time::SystemTime;
+
+use common::ProcessState;
+
 use crate::config::Signal;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -8,6 +12,24 @@ pub enum ProcessStatus {
     GracefulExit(u32),
     Killed(Signal),
     FailedExit(u32),
+    /// Exiting, either asked to through its stop ladder and killed past its
+    /// last step, or on its own after saying `STOPPING=1`
+    Stopping,
+    /// Exited after being asked to, never restarted
+    Stopped,
+}
+
+impl ProcessStatus {
+    /// Whether a run ended in this status, FailedToStart never ran.
+    pub fn has_exited(&self) -> bool {
+        matches!(
+            self,
+            ProcessStatus::GracefulExit(_)
+                | ProcessStatus::FailedExit(_)
+                | ProcessStatus::Killed(_)
+                | ProcessStatus::Stopped
+        )
+    }
 }
 
 impl std::fmt::Display for ProcessStatus {
@@ -15,3 +37,62 @@ impl std::fmt::Display for ProcessStatus {
         f.pad(&format!("{:?}", self))
     }
 }
+
+impl From<ProcessStatus> for ProcessState {
+    fn from(status: ProcessStatus) -> Self {
+        match status {
+            ProcessStatus::Starting => ProcessState::Starting,
+            ProcessStatus::FailedToStart => ProcessState::Fatal,
+            ProcessStatus::Active => ProcessState::Active,
+            ProcessStatus::GracefulExit(code) => ProcessState::Exited {
+                code,
+                success: true,
+            },
+            ProcessStatus::FailedExit(code) => ProcessState::Exited {
+                code,
+                success: false,
+            },
+            ProcessStatus::Killed(signal) => ProcessState::Killed {
+                signal: format!("{signal:?}"),
+            },
+            ProcessStatus::Stopping => ProcessState::Stopping,
+            ProcessStatus::Stopped => ProcessState::Stopped,
+        }
+    }
+}
+
+/// A state a process went through, not yet published as an event.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Transition {
+    pub state: ProcessState,
+    pub pid:   Option<u32>,
+    pub at:    SystemTime,
+}
+
+/// How and when a process run came to an end.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ExitRecord {
+    pub status:    ProcessStatus,
+    pub at:        SystemTime,
+    /// Last step taken to stop it, None unless the daemon stopped it
+    pub stop_step: Option<StopStep>,
+}
+
+/// A step of the stop ladder taken to stop a process.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct StopStep {
+    /// Position in the ladder, past its end once the process was killed
+    pub index:  usize,
+    /// None when the `stop_command` ran instead, or the process said it was
+    /// stopping
+    pub signal: Option<Signal>,
+}
+
+impl std::fmt::Display for StopStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.signal {
+            Some(signal) => write!(f, "step {} ({signal:?})", self.index + 1),
+            None => write!(f, "step {} (no signal)", self.index + 1),
+        }
+    }
+}
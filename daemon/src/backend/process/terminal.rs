@@ -0,0 +1,86 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+
+use common::syscall;
+
+/// Master side of the pseudo-terminal a process runs on. Owned by the daemon
+/// for as long as the process lives; dropping it hangs up the child.
+#[derive(Debug)]
+pub struct Terminal {
+    pub master: File,
+}
+
+impl Terminal {
+    /// Allocates a new pseudo-terminal, returning the master and the slave the
+    /// child should use as its stdio.
+    pub fn open() -> io::Result<(Terminal, File)> {
+        let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+        let fd = syscall!(posix_openpt(flags))?;
+        let master = unsafe { File::from_raw_fd(fd) };
+        syscall!(grantpt(fd))?;
+        syscall!(unlockpt(fd))?;
+
+        let mut name = [0 as libc::c_char; 128];
+        let res = unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) };
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res));
+        }
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(name.to_string_lossy().as_ref())?;
+
+        let flags = syscall!(fcntl(fd, libc::F_GETFL))?;
+        syscall!(fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+        Ok((Terminal { master }, slave))
+    }
+
+    /// Reads everything the child wrote so far without blocking.
+    pub fn read_available(&self) -> io::Result<Vec<u8>> {
+        let mut output = vec![];
+        let mut buf = [0_u8; 4096];
+        loop {
+            match (&self.master).read(&mut buf) {
+                Ok(0) => break,
+                Ok(bytes) => output.extend_from_slice(&buf[..bytes]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // linux reports a hung up slave as EIO
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(output)
+    }
+
+    pub fn resize(&self, rows: u16, cols: u16) -> io::Result<()> {
+        let size = libc::winsize {
+            ws_row:    rows,
+            ws_col:    cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        syscall!(ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size))?;
+        Ok(())
+    }
+
+    /// Sends SIGINT to the foreground process group of the terminal, as a
+    /// Ctrl-C typed on a real one would.
+    pub fn interrupt(&self) -> io::Result<()> {
+        let pgrp = syscall!(tcgetpgrp(self.master.as_raw_fd()))?;
+        syscall!(kill(-pgrp, libc::SIGINT))?;
+        Ok(())
+    }
+}
+
+/// Makes the terminal received on stdin the controlling terminal of the
+/// child. Runs between fork and exec.
+pub fn acquire_controlling_terminal() -> io::Result<()> {
+    syscall!(setsid())?;
+    syscall!(ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY, 0))?;
+    Ok(())
+}
@@ -1,9 +1,14 @@
-use std::process::Command;
+use std::os::unix::net::UnixDatagram;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus};
+
+use common::ProcessState;
 
 // use super::time_stub;
-use crate::backend::process::{Process, ProcessStatus};
+use crate::backend::process::{Process, ProcessStatus, StopStep};
 use crate::backend::program::Program;
-use crate::config::{ProgramConfig, RestartOption};
+use crate::config::structs::{IOHandler, KnownHandler};
+use crate::config::{ProgramConfig, ReadyOption, RestartOption, Signal, StopSignal};
 
 #[test]
 fn test_process_should_spawn_program_immediately() {
@@ -56,7 +61,7 @@ fn test_status_should_be_gracefulexit_when_program_exits_with_a_status_specified
     // start as starting
     assert_eq!(process.status, ProcessStatus::Starting);
 
-    process.child.as_mut().unwrap().wait().unwrap();
+    wait_for_exit(&mut process);
 
     process.update_status(&config);
 
@@ -80,7 +85,7 @@ fn test_status_should_be_failed_exit_when_program_exits_with_a_status_not_specif
     let mut process = Process::start(&mut program.command);
     // then
     assert_eq!(process.status, ProcessStatus::Starting);
-    process.child.as_mut().unwrap().wait().unwrap();
+    wait_for_exit(&mut process);
     process.update_status(&config);
     // then change to failed
     assert_eq!(process.status, ProcessStatus::FailedExit(1));
@@ -100,7 +105,7 @@ fn test_status_should_be_killed_when_program_is_killed() {
     // then
     assert_eq!(process.status, ProcessStatus::Starting);
     process.child.as_mut().unwrap().kill().unwrap();
-    process.child.as_mut().unwrap().wait().unwrap();
+    wait_for_exit(&mut process);
     process.update_status(&config);
     // then change to killed
     assert_eq!(
@@ -134,10 +139,10 @@ fn test_kill_enum_should_be_equivalent_to_the_signal_sent() {
     assert_eq!(process.status, ProcessStatus::Starting);
     send_signal(
         process.child.as_mut().unwrap().id(),
-        crate::config::Signal::SIGINT as i32,
+        crate::config::Signal::SIGINT.into(),
     );
 
-    process.child.as_mut().unwrap().wait().unwrap();
+    wait_for_exit(&mut process);
     process.update_status(&config);
     // then change to killed
     assert_eq!(process.status, ProcessStatus::Killed(crate::config::Signal::SIGINT));
@@ -186,6 +191,48 @@ fn test_status_should_be_active_if_enough_time_has_passed_since_start() {
     assert_eq!(process.status, ProcessStatus::Active);
 }
 
+#[test]
+fn notifying_process_should_be_active_once_ready_and_aborted_without_heartbeat() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("sleep");
+    config.args.push(String::from("10"));
+    config.restart = RestartOption::NEVER;
+    config.stdout = IOHandler::KNOWN(KnownHandler::DISCARD);
+    config.stderr = IOHandler::KNOWN(KnownHandler::DISCARD);
+    config.ready = ReadyOption::Notify;
+    config.watchdog = 5;
+    let mut program = Program::build_from((&config_name, &config));
+    program.create_output_files();
+    program.update_process_count();
+    let notify = |program: &Program, message: &[u8]| {
+        let socket = program.notify[0].as_ref().unwrap().path();
+        UnixDatagram::unbound()
+            .unwrap()
+            .send_to(message, socket)
+            .unwrap();
+    };
+    Instant::advance(1);
+    program.update_process_status();
+    assert_eq!(program.processes[0].status, ProcessStatus::Starting);
+
+    // when
+    notify(&program, b"READY=1\nSTATUS=serving");
+    program.update_process_status();
+    // then
+    assert_eq!(program.processes[0].status, ProcessStatus::Active);
+    assert_eq!(program.processes[0].notified.status.as_deref(), Some("serving"));
+
+    // when
+    Instant::advance(6);
+    program.update_process_status();
+    wait_for_exit(&mut program.processes[0]);
+    program.update_process_status();
+    // then
+    assert_eq!(program.processes[0].status, ProcessStatus::Killed(Signal::SIGABRT));
+}
+
 #[test]
 fn process_should_restart_if_it_exits_with_an_error_code_and_restart_option_is_always()
 {
@@ -200,13 +247,269 @@ fn process_should_restart_if_it_exits_with_an_error_code_and_restart_option_is_a
 
     // when
     let mut process = Process::start(&mut program.command);
-    process.child.as_mut().unwrap().wait().unwrap();
+    wait_for_exit(&mut process);
     process.update_status(&config);
 
     // then
     assert!(process.should_restart);
 }
 
+#[test]
+fn process_should_remember_its_exits_and_count_restarts() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("bash");
+    config.args.push(String::from("-c"));
+    config.args.push(String::from("exit 1"));
+    config.restart = RestartOption::ALWAYS;
+    let mut program = Program::build_from((&config_name, &config));
+
+    // when
+    let mut process = Process::start(&mut program.command);
+    wait_for_exit(&mut process);
+    process.update_status(&config);
+    process.restart(&mut program.command);
+
+    // then
+    assert_eq!(process.restart_count, 1);
+    assert_eq!(process.status, ProcessStatus::Starting);
+    assert_eq!(
+        process.last_exit().map(|exit| exit.status),
+        Some(ProcessStatus::FailedExit(1))
+    );
+}
+
+#[test]
+fn process_should_record_its_transitions_for_subscribers() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("bash");
+    config.args.push(String::from("-c"));
+    config.args.push(String::from("exit 2"));
+    let mut program = Program::build_from((&config_name, &config));
+
+    // when
+    let mut process = Process::start(&mut program.command);
+    wait_for_exit(&mut process);
+    process.update_status(&config);
+    process.update_status(&config);
+
+    // then
+    let states: Vec<ProcessState> = process
+        .transitions
+        .drain(..)
+        .map(|transition| transition.state)
+        .collect();
+    assert_eq!(
+        states,
+        [
+            ProcessState::Starting,
+            ProcessState::Exited {
+                code:    2,
+                success: false,
+            }
+        ]
+    );
+}
+
+#[test]
+fn process_ignoring_its_stop_signal_should_be_killed_after_ttk() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("bash");
+    config.args.push(String::from("-c"));
+    config.args.push(String::from("trap '' TERM; sleep 10"));
+    config.ttk = 3;
+    let mut program = Program::build_from((&config_name, &config));
+    let mut process = Process::start(&mut program.command);
+    // let bash install its trap
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    // when
+    process.stop(&config).unwrap();
+    process.update_status(&config);
+    assert_eq!(process.status, ProcessStatus::Stopping);
+    Instant::advance(4);
+    process.update_status(&config);
+    wait_for_exit(&mut process);
+    process.update_status(&config);
+
+    // then
+    assert_eq!(process.status, ProcessStatus::Stopped);
+    assert_eq!(
+        process.last_exit().map(|exit| exit.status),
+        Some(ProcessStatus::Killed(Signal::SIGKILL))
+    );
+    assert!(!process.should_restart);
+}
+
+#[test]
+fn process_should_be_stopped_with_each_stop_signal_in_turn() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("bash");
+    config.args.push(String::from("-c"));
+    config.args.push(String::from("trap '' TERM; sleep 10"));
+    config.stop_signals = vec![
+        StopSignal {
+            signal: Signal::SIGTERM,
+            wait:   20,
+        },
+        StopSignal {
+            signal: Signal::SIGUSR1,
+            wait:   10,
+        },
+    ];
+    let mut program = Program::build_from((&config_name, &config));
+    let mut process = Process::start(&mut program.command);
+    // let bash install its trap
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    // when
+    process.stop(&config).unwrap();
+    Instant::advance(21);
+    process.update_status(&config);
+    wait_for_exit(&mut process);
+    process.update_status(&config);
+
+    // then
+    let exit = process.last_exit().unwrap();
+    assert_eq!(exit.status, ProcessStatus::Killed(Signal::SIGUSR1));
+    assert_eq!(
+        exit.stop_step,
+        Some(StopStep {
+            index:  1,
+            signal: Some(Signal::SIGUSR1),
+        })
+    );
+}
+
+#[test]
+fn process_stopping_on_its_own_should_be_waited_for_until_asked_to_stop() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("sleep");
+    config.args.push(String::from("10"));
+    config.stdout = IOHandler::KNOWN(KnownHandler::DISCARD);
+    config.stderr = IOHandler::KNOWN(KnownHandler::DISCARD);
+    config.ready = ReadyOption::Notify;
+    config.ttk = 3;
+    let mut program = Program::build_from((&config_name, &config));
+    program.create_output_files();
+    program.update_process_count();
+    let socket = program.notify[0].as_ref().unwrap().path();
+    UnixDatagram::unbound()
+        .unwrap()
+        .send_to(b"STOPPING=1", socket)
+        .unwrap();
+
+    // when
+    program.update_process_status();
+    Instant::advance(4);
+    program.update_process_status();
+    // then
+    assert_eq!(program.processes[0].status, ProcessStatus::Stopping);
+    assert!(program.processes[0].stop_step.is_none());
+
+    // when
+    program.stop(0);
+    // then
+    assert_eq!(
+        program.processes[0].stop_step.and_then(|step| step.signal),
+        Some(Signal::SIGQUIT)
+    );
+    wait_for_exit(&mut program.processes[0]);
+}
+
+#[test]
+fn process_should_receive_input_written_to_its_stdin() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("bash");
+    config.args.push(String::from("-c"));
+    config.args.push(String::from("read code; exit $code"));
+    config.restart = RestartOption::NEVER;
+    config.allow_stdin = true;
+    let mut program = Program::build_from((&config_name, &config));
+    let mut process = Process::start(&mut program.command);
+
+    // when
+    process.write_stdin(b"3\n").unwrap();
+    wait_for_exit(&mut process);
+    process.update_status(&config);
+
+    // then
+    assert_eq!(process.status, ProcessStatus::FailedExit(3));
+}
+
+#[test]
+fn process_without_stdin_pipe_should_refuse_input() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("sleep");
+    config.args.push(String::from("1"));
+    config.restart = RestartOption::NEVER;
+    let mut program = Program::build_from((&config_name, &config));
+    // when
+    let mut process = Process::start(&mut program.command);
+    // then
+    assert!(process.write_stdin(b"input\n").is_err());
+}
+
+#[test]
+fn process_should_keep_the_input_its_stdin_does_not_take_yet() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("sleep");
+    config.args.push(String::from("10"));
+    config.restart = RestartOption::NEVER;
+    config.allow_stdin = true;
+    let mut program = Program::build_from((&config_name, &config));
+    let mut process = Process::start(&mut program.command);
+    // when
+    process.write_stdin(&[b'x'; 40_000]).unwrap();
+    process.write_stdin(&[b'x'; 40_000]).unwrap();
+    // then
+    assert!(!process.pending_input.is_empty());
+    assert!(process.write_stdin(&[b'x'; 60_000]).is_err());
+    process.send_signal(Signal::SIGKILL).unwrap();
+    wait_for_exit(&mut process);
+}
+
+#[test]
+fn process_started_on_terminal_should_have_a_tty() {
+    // given
+    let config_name = String::from("test");
+    let mut config = ProgramConfig::new();
+    config.command = String::from("tty");
+    config.restart = RestartOption::NEVER;
+    config.tty = true;
+    let mut program = Program::build_from((&config_name, &config));
+    // when
+    let mut process = program.spawn(0);
+    wait_for_exit(&mut process);
+    // then
+    let output = process.terminal.as_ref().unwrap().read_available().unwrap();
+    assert!(String::from_utf8_lossy(&output).starts_with("/dev/pts/"));
+}
+
+/// Waits for the process to exit and hands it the status, as the daemon does
+/// once it reaped it.
+pub fn wait_for_exit(process: &mut Process) {
+    let mut status = 0;
+    let pid = process.pid().unwrap() as libc::pid_t;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    process.reaped(ExitStatus::from_raw(status));
+}
+
 static mut TICK: u64 = 0;
 
 #[derive(Debug)]
@@ -249,4 +552,6 @@ pub struct Duration {
 
 impl Duration {
     pub(crate) fn from_secs(seconds: u64) -> Duration { Duration { seconds } }
+
+    pub(crate) fn as_secs(&self) -> u64 { self.seconds }
 }
@@ -1,27 +1,63 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
-use std::env;
+use std::collections::{HashMap, VecDeque};
 use std::fs::{File, OpenOptions};
-use std::process::Command;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::{env, fs, io, mem};
 
-use super::process::Process;
+use common::Event;
+use logger::{error, warning};
+use regex::Regex;
+
+use super::hooks::Hook;
+use super::logs::Stream;
+use super::notify::NotifySocket;
+use super::process::{acquire_controlling_terminal, Process, ProcessStatus};
+use super::restart::RollingRestart;
+use super::sockets::ListeningSocket;
 use crate::config::structs::{IOHandler, KnownHandler};
-use crate::config::ProgramConfig;
+use crate::config::{ConfigError, ProgramConfig, ReadyOption};
+use crate::signal_handling::unblock_signals;
 
 pub struct Program {
-    pub config_name: String,
-    pub config:      ProgramConfig,
-    pub command:     Command,
-    pub stdout:      Vec<Option<File>>,
-    pub stderr:      Vec<Option<File>>,
-    pub processes:   Vec<Process>,
+    pub config_name:    String,
+    pub config:         ProgramConfig,
+    pub command:        Command,
+    pub stdout:         Vec<Option<File>>,
+    pub stderr:         Vec<Option<File>>,
+    /// Notify sockets of the instances with `ready: notify`
+    pub notify:         Vec<Option<NotifySocket>>,
+    /// Sockets listened on for the instances, see `open_sockets`
+    pub sockets:        Vec<ListeningSocket>,
+    /// The `stdout_matches` pattern, compiled
+    pub ready_pattern:  Option<Regex>,
+    pub processes:      Vec<Process>,
+    /// Events waiting for an instance of an event listener
+    pub pending_events: VecDeque<(u64, Event)>,
+    /// Number of the next event to queue for an event listener
+    pub event_cursor:   Option<u64>,
+    /// Instances left to restart, see `restart_instances`
+    pub restarting:     Option<RollingRestart>,
 }
 
 impl Program {
     pub fn build_from(
         (config_name, command_config): (&String, &ProgramConfig),
     ) -> Program {
-        let mut command = Command::new(&command_config.command);
+        let mut command = match command_config.sockets.is_empty() {
+            true => Command::new(&command_config.command),
+            // LISTEN_PID is the pid of the program, only known once forked
+            false => {
+                let mut shell = Command::new("sh");
+                shell.args([
+                    "-c",
+                    "export LISTEN_PID=$$; exec \"$0\" \"$@\"",
+                    &command_config.command,
+                ]);
+                shell
+            }
+        };
         command
             .current_dir(&command_config.workdir)
             .args(&command_config.args)
@@ -33,6 +69,14 @@ impl Program {
                     .map(|(var, value)| (var.to_string(), value.to_string()))
                     .collect::<HashMap<String, String>>(),
             );
+        unsafe { command.pre_exec(unblock_signals) };
+        if command_config.tty {
+            unsafe { command.pre_exec(acquire_controlling_terminal) };
+        } else if command_config.event_listener {
+            command.stdin(Stdio::piped()).stdout(Stdio::piped());
+        } else if command_config.allow_stdin {
+            command.stdin(Stdio::piped());
+        }
         Program {
             config_name: config_name.to_string(),
             config: command_config.clone(),
@@ -40,82 +84,344 @@ impl Program {
             processes: vec![],
             stdout: vec![],
             stderr: vec![],
+            notify: vec![],
+            sockets: vec![],
+            ready_pattern: None,
+            pending_events: VecDeque::new(),
+            event_cursor: None,
+            restarting: None,
         }
     }
 
     pub fn create_output_files(&mut self) {
-        for i in 0..self.config.processes {
-            let mut opts = OpenOptions::new();
-            opts.create(true).write(true).append(true).read(true);
-            if let IOHandler::FILE(filename) = &self.config.stdout {
-                self.stdout.push(Some(opts.open(filename).unwrap()));
-            }
-            if let IOHandler::KNOWN(handler) = &self.config.stdout {
-                match handler {
-                    KnownHandler::DEFAULT => {
-                        let file = Some(Self::default_stdout(&self.config, i));
-                        self.stdout.push(file);
-                    }
-                    KnownHandler::DISCARD => self.stdout.push(None),
-                }
+        for i in self.stdout.len()..self.config.processes {
+            let stdout = self.open_output(Stream::Stdout, i);
+            self.stdout.push(stdout);
+            let stderr = self.open_output(Stream::Stderr, i);
+            self.stderr.push(stderr);
+        }
+        for i in self.notify.len()..self.config.processes {
+            let socket = match self.config.ready {
+                ReadyOption::Notify => NotifySocket::bind(&self.config_name, i)
+                    .map_err(|e| error!("{}[{i}] notify socket: {e}", self.config_name))
+                    .ok(),
+                ReadyOption::Started => None,
+            };
+            self.notify.push(socket);
+        }
+    }
+
+    /// Where a stream of an instance is written to, None if it is discarded.
+    pub fn output_path(&self, stream: Stream, instance: usize) -> Option<PathBuf> {
+        let handler = match stream {
+            Stream::Stdout => &self.config.stdout,
+            Stream::Stderr => &self.config.stderr,
+        };
+        match handler {
+            IOHandler::FILE(filename) => Some(PathBuf::from(filename)),
+            IOHandler::KNOWN(KnownHandler::DISCARD) => None,
+            IOHandler::KNOWN(KnownHandler::DEFAULT) => Some(PathBuf::from(format!(
+                "{}/logs/{}/p{}/{}",
+                env::current_dir().unwrap().display(),
+                self.config_name,
+                instance,
+                stream
+            ))),
+        }
+    }
+
+    fn open_output(&self, stream: Stream, instance: usize) -> Option<File> {
+        let path = self.output_path(stream, instance)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .unwrap_or_else(|e| error!("{}: {e}", dir.display()));
+        }
+        let mut opts = OpenOptions::new();
+        opts.create(true).append(true);
+        match opts.open(&path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                error!("{}: {e}", path.display());
+                None
             }
         }
+    }
 
-        for i in 0..self.config.processes {
-            let mut opts = OpenOptions::new();
-            opts.create(true).write(true).append(true).read(true);
-            if let IOHandler::FILE(filename) = &self.config.stderr {
-                self.stderr.push(Some(opts.open(filename).unwrap()));
+    /// Points the output of the next spawned process to the files of its
+    /// instance. Programs without output files keep the daemon's.
+    fn redirect_output(&mut self, instance: usize) {
+        if self.config.tty {
+            return;
+        }
+        let outputs = [
+            (self.stdout.get(instance), Stream::Stdout),
+            (self.stderr.get(instance), Stream::Stderr),
+        ];
+        for (output, stream) in outputs {
+            if self.config.event_listener && stream == Stream::Stdout {
+                continue;
             }
-            if let IOHandler::KNOWN(handler) = &self.config.stderr {
-                match handler {
-                    KnownHandler::DEFAULT => {
-                        let file = Some(Self::default_stderr(&self.config, i));
-                        self.stderr.push(file);
+            let stdio = match output {
+                Some(Some(file)) => match file.try_clone() {
+                    Ok(file) => Stdio::from(file),
+                    Err(e) => {
+                        error!("{}[{instance}] {stream}: {e}", self.config_name);
+                        Stdio::null()
                     }
-                    KnownHandler::DISCARD => self.stderr.push(None),
-                }
+                },
+                Some(None) => Stdio::null(),
+                None => continue,
+            };
+            match stream {
+                Stream::Stdout => self.command.stdout(stdio),
+                Stream::Stderr => self.command.stderr(stdio),
+            };
+        }
+    }
+
+    /// Points the next spawned process to the notify socket of its instance,
+    /// forgetting what an earlier process of the instance sent.
+    fn prepare_notify(&mut self, instance: usize) {
+        let Some(Some(socket)) = self.notify.get(instance) else {
+            return;
+        };
+        if let Err(e) = socket.read() {
+            error!("{}[{instance}] notify socket: {e}", self.config_name);
+        }
+        self.command.env("NOTIFY_SOCKET", socket.path());
+        if self.config.watchdog > 0 {
+            let usec = self.config.watchdog as u64 * 1_000_000;
+            self.command.env("WATCHDOG_USEC", usec.to_string());
+        }
+    }
+
+    /// Hands each instance what its process sent to its notify socket.
+    fn read_notifications(&mut self) {
+        for (i, socket) in self.notify.iter().enumerate() {
+            let (Some(socket), Some(process)) = (socket, self.processes.get_mut(i))
+            else {
+                continue;
+            };
+            match socket.read() {
+                Ok(notifications) => notifications
+                    .into_iter()
+                    .for_each(|notification| process.notify(notification)),
+                Err(e) => error!("{}[{i}] notify socket: {e}", self.config_name),
             }
         }
     }
 
-    fn default_stdout(config: &ProgramConfig, process_id: usize) -> File {
-        let name = format!(
-            "{}/logs/{}/p{}/stdout",
-            env::current_dir().unwrap().display(),
-            config.command,
-            process_id
-        );
-        File::options()
-            .create(true)
-            .append(true)
-            .open(name)
-            .unwrap()
+    /// A new process for an instance, started once its `pre_start` hook
+    /// succeeded if it has one.
+    pub fn spawn(&mut self, instance: usize) -> Process {
+        match self.start_hook(Hook::PreStart, instance) {
+            Ok(None) => self.start_process(instance),
+            Ok(Some(hook)) => Process {
+                child: Err(io::Error::other("waiting for pre_start")),
+                status: ProcessStatus::Starting,
+                hooks: vec![hook],
+                ..Process::default()
+            },
+            Err(e) => {
+                let mut process = Process::default();
+                process.fail_start(&self.config, e);
+                process
+            }
+        }
+    }
+
+    fn start_process(&mut self, instance: usize) -> Process {
+        self.redirect_output(instance);
+        self.prepare_notify(instance);
+        let output_offset = self.output_end(instance);
+        let mut process = match self.config.tty {
+            true => Process::start_on_terminal(&mut self.command),
+            false => Process::start(&mut self.command),
+        };
+        process.notified.output_offset = output_offset;
+        process
+    }
+
+    /// Starts an instance whose `pre_start` hook succeeded, again if it ran
+    /// before.
+    pub(super) fn launch(&mut self, instance: usize) {
+        let process = &self.processes[instance];
+        if !process.should_restart && !process.should_try_again {
+            let process = self.start_process(instance);
+            self.replace(instance, process);
+            return;
+        }
+        self.redirect_output(instance);
+        self.prepare_notify(instance);
+        let output_offset = self.output_end(instance);
+        let process = &mut self.processes[instance];
+        if process.should_restart {
+            process.restart(&mut self.command);
+        } else {
+            process.try_start_again(&mut self.command);
+        }
+        process.notified.output_offset = output_offset;
     }
 
-    fn default_stderr(config: &ProgramConfig, process_id: usize) -> File {
-        let name = format!(
-            "{}/logs/{}/p{}/stderr",
-            env::current_dir().unwrap().display(),
-            config.command,
-            process_id
-        );
-        File::options()
-            .create(true)
-            .append(true)
-            .open(name)
-            .unwrap()
+    /// Replaces the process of an instance, keeping the transitions of the
+    /// old one for them to be published and its hooks for them to be reaped.
+    pub(super) fn replace(&mut self, instance: usize, mut process: Process) {
+        let old = &mut self.processes[instance];
+        process
+            .transitions
+            .splice(0..0, mem::take(&mut old.transitions));
+        process.hooks.extend(mem::take(&mut old.hooks));
+        self.processes[instance] = process;
     }
 
     pub fn update_process_status(&mut self) {
-        self.processes.iter_mut().for_each(|p| {
-            p.update_status(&self.config);
-            if p.should_restart {
-                p.restart(&mut self.command);
-            } else if p.should_try_again {
-                p.try_start_again(&mut self.command);
+        self.read_notifications();
+        self.check_readiness();
+        for i in 0..self.processes.len() {
+            if let Err(e) = self.processes[i].flush_stdin() {
+                warning!("{}[{i}] input dropped: {e}", self.config_name);
             }
-        })
+            self.update_hooks(i);
+            // nothing runs until its pre_start hook ended
+            if self.processes[i].runs_hook(Hook::PreStart) {
+                continue;
+            }
+            let previous = self.processes[i].status;
+            self.processes[i].update_status(&self.config);
+            self.run_transition_hook(i, previous);
+            let process = &mut self.processes[i];
+            // surplus instances are not started again
+            if i >= self.config.processes {
+                process.should_restart = false;
+                process.should_try_again = false;
+            }
+            // the hooks of the last run end before the next one starts
+            if !(process.should_restart || process.should_try_again)
+                || !process.hooks.is_empty()
+            {
+                continue;
+            }
+            match self.start_hook(Hook::PreStart, i) {
+                Ok(None) => self.launch(i),
+                Ok(Some(hook)) => self.processes[i].hooks.push(hook),
+                Err(e) => self.processes[i].fail_start(&self.config, e),
+            }
+        }
+        // surplus instances leave once they stopped
+        while self.processes.len() > self.config.processes
+            && self
+                .processes
+                .last()
+                .is_some_and(|p| !p.is_running() && p.hooks.is_empty())
+        {
+            self.processes.pop();
+        }
+        self.advance_restart();
+    }
+
+    /// Changes the number of instances, the surplus ones are stopped, highest
+    /// instance first.
+    pub fn scale(&mut self, count: usize) -> Result<(), ConfigError> {
+        let mut config = self.config.clone();
+        config.processes = count;
+        config.validate()?;
+        // a stopped one is started again once its hooks ended
+        if let Some(i) = (0..count.min(self.processes.len())).find(|&i| {
+            let process = &self.processes[i];
+            process.status == ProcessStatus::Stopping
+                || process.status == ProcessStatus::Stopped && !process.hooks.is_empty()
+        }) {
+            return Err(format!("{}:{i} is still stopping", self.config_name).into());
+        }
+        self.config.processes = count;
+        self.create_output_files();
+        for i in 0..count.min(self.processes.len()) {
+            if self.processes[i].status == ProcessStatus::Stopped {
+                let process = self.spawn(i);
+                self.replace(i, process);
+            }
+        }
+        self.update_process_count();
+        Ok(())
+    }
+
+    /// Starts the hook of the state an instance reached since `previous`.
+    fn run_transition_hook(&mut self, instance: usize, previous: ProcessStatus) {
+        let status = self.processes[instance].status;
+        let hook = match previous {
+            _ if previous == status => return,
+            _ if status == ProcessStatus::Active => Hook::PostStart,
+            ProcessStatus::Starting
+            | ProcessStatus::Active
+            | ProcessStatus::Stopping
+                if status.has_exited() =>
+            {
+                Hook::PostStop
+            }
+            _ => return,
+        };
+        match self.start_hook(hook, instance) {
+            Ok(hook) => self.processes[instance].hooks.extend(hook),
+            Err(e) => warning!("{}[{instance}] {e}", self.config_name),
+        }
+    }
+
+    /// Asks an instance to exit once its `pre_stop` hook ended. One waiting
+    /// for its `pre_start` hook is not started.
+    pub fn stop(&mut self, instance: usize) {
+        let Some(process) = self.processes.get_mut(instance) else {
+            return;
+        };
+        if process.runs_hook(Hook::PreStart) {
+            process.hooks.retain_mut(|running| {
+                let pre_start = running.hook == Hook::PreStart;
+                if pre_start {
+                    running.kill();
+                }
+                !pre_start
+            });
+            process.cancel_start();
+            return;
+        }
+        // one stopping on its own is only signaled once asked to stop
+        let stopping_alone = process.status == ProcessStatus::Stopping
+            && process.stop_step.is_none()
+            && process.hooks.is_empty();
+        if stopping_alone {
+            return self.ask_to_exit(instance);
+        }
+        if !matches!(process.status, ProcessStatus::Starting | ProcessStatus::Active) {
+            return;
+        }
+        match self.start_hook(Hook::PreStop, instance) {
+            Ok(Some(hook)) => {
+                let process = &mut self.processes[instance];
+                process.hold_stop();
+                process.hooks.push(hook);
+            }
+            Ok(None) => self.ask_to_exit(instance),
+            Err(e) => {
+                warning!("{}[{instance}] {e}", self.config_name);
+                self.ask_to_exit(instance);
+            }
+        }
+    }
+
+    /// Asks an instance to exit with its `stop_command`, or else the first
+    /// of its stop signals.
+    pub(super) fn ask_to_exit(&mut self, instance: usize) {
+        let mut result = self.processes[instance].stop(&self.config);
+        if self.config.stop_command.is_some()
+            && self.processes[instance].status == ProcessStatus::Stopping
+        {
+            // the stop ladder goes on if the process outlives it
+            result = self
+                .start_hook(Hook::StopCommand, instance)
+                .map(|hook| self.processes[instance].hooks.extend(hook));
+        }
+        if let Err(e) = result {
+            error!("{}[{instance}] stop failed: {e}", self.config_name);
+        }
     }
 
     pub fn update_process_count(&mut self) {
@@ -123,13 +429,14 @@ impl Program {
         let desired_count = self.config.processes;
         match current_count.cmp(&desired_count) {
             Ordering::Less => {
-                for _ in 0..desired_count - current_count {
-                    self.processes.push(Process::start(&mut self.command));
+                for i in current_count..desired_count {
+                    let process = self.spawn(i);
+                    self.processes.push(process);
                 }
             }
             Ordering::Greater => {
-                for _ in 0..current_count - desired_count {
-                    self.processes.pop();
+                for i in (desired_count..current_count).rev() {
+                    self.stop(i);
                 }
             }
             _ => {}
@@ -139,7 +446,8 @@ impl Program {
 
 #[cfg(test)]
 mod tests {
-    use super::Program;
+    use super::{ProcessStatus, Program};
+    use crate::backend::process::tests::wait_for_exit;
     use crate::config::ProgramConfig;
 
     #[test]
@@ -168,6 +476,11 @@ mod tests {
         assert_eq!(program.processes.len(), 3);
         program.config.processes = 1;
         program.update_process_count();
+        assert_eq!(program.processes[2].status, ProcessStatus::Stopping);
+        for process in &mut program.processes {
+            wait_for_exit(process);
+        }
+        program.update_process_status();
         assert_eq!(program.processes.len(), 1);
     }
 }
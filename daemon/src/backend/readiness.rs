@@ -0,0 +1,279 @@
+//! Readiness of processes that do not tell it, found by the daemon while
+//! they start from their `ready_when`: a line of their stdout matching a
+//! regex, a port of localhost accepting connections or a file existing.
+//! Only the loopback address is probed for `port_open`, the connection is
+//! made without blocking and looked at on the next passes.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+
+use common::syscall;
+use logger::error;
+use regex::Regex;
+
+use super::logs::Stream;
+use super::{ProcessStatus, Program};
+use crate::config::ReadyWhen;
+
+/// A connection to a port of localhost in progress.
+#[derive(Debug)]
+pub struct PortProbe(OwnedFd);
+
+impl PortProbe {
+    fn connect(port: u16) -> io::Result<PortProbe> {
+        let flags = libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let fd = syscall!(socket(libc::AF_INET, flags, 0))?;
+        // safe as the descriptor was just created and is owned by nothing else
+        let probe = PortProbe(unsafe { OwnedFd::from_raw_fd(fd) });
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port:   port.to_be(),
+            sin_addr:   libc::in_addr {
+                s_addr: u32::from(Ipv4Addr::LOCALHOST).to_be(),
+            },
+            sin_zero:   [0; 8],
+        };
+        match syscall!(connect(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of_val(&addr) as libc::socklen_t,
+        )) {
+            Ok(_) => Ok(probe),
+            Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => Ok(probe),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the connection was accepted, None while it is in progress.
+    fn accepted(&self) -> io::Result<Option<bool>> {
+        let mut pollfd = libc::pollfd {
+            fd:      self.0.as_raw_fd(),
+            events:  libc::POLLOUT,
+            revents: 0,
+        };
+        if syscall!(poll(&mut pollfd, 1, 0))? == 0 {
+            return Ok(None);
+        }
+        let mut error: libc::c_int = 0;
+        let mut length = mem::size_of_val(&error) as libc::socklen_t;
+        syscall!(getsockopt(
+            self.0.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut error as *mut libc::c_int as *mut libc::c_void,
+            &mut length,
+        ))?;
+        Ok(Some(error == 0))
+    }
+}
+
+/// Whether the port accepted the connection of the probe started on an
+/// earlier pass, a new one is started once it was refused.
+fn port_open(probe: &mut Option<PortProbe>, port: u16) -> bool {
+    let Some(current) = probe else {
+        *probe = match PortProbe::connect(port) {
+            Ok(started) => Some(started),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => None,
+            Err(e) => {
+                error!("port {port}: {e}");
+                None
+            }
+        };
+        return false;
+    };
+    match current.accepted() {
+        Ok(None) => false,
+        Ok(Some(accepted)) => {
+            *probe = None;
+            accepted
+        }
+        Err(e) => {
+            error!("port {port}: {e}");
+            *probe = None;
+            false
+        }
+    }
+}
+
+impl Program {
+    /// Marks ready the starting instances that met their `ready_when`.
+    pub(super) fn check_readiness(&mut self) {
+        let Some(ready_when) = self.config.ready_when.clone() else {
+            return;
+        };
+        if let ReadyWhen::StdoutMatches(pattern) = &ready_when {
+            if self
+                .ready_pattern
+                .as_ref()
+                .is_none_or(|re| re.as_str() != pattern)
+            {
+                self.ready_pattern = Regex::new(pattern)
+                    .map_err(|e| error!("{}: {e}", self.config_name))
+                    .ok();
+            }
+        }
+        for i in 0..self.processes.len() {
+            let process = &self.processes[i];
+            if process.status != ProcessStatus::Starting || process.notified.ready {
+                continue;
+            }
+            let ready = match &ready_when {
+                ReadyWhen::StdoutMatches(_) => self.output_matches(i),
+                ReadyWhen::PortOpen(port) => {
+                    port_open(&mut self.processes[i].notified.port_probe, *port)
+                }
+                ReadyWhen::FileExists(path) => {
+                    Path::new(&self.config.workdir).join(path).exists()
+                }
+            };
+            self.processes[i].notified.ready = ready;
+        }
+    }
+
+    /// Whether a line the instance wrote since it started matches the
+    /// `stdout_matches` pattern. A line is looked at once it is complete.
+    fn output_matches(&mut self, instance: usize) -> bool {
+        let (Some(re), Some(path)) =
+            (&self.ready_pattern, self.output_path(Stream::Stdout, instance))
+        else {
+            return false;
+        };
+        let notified = &mut self.processes[instance].notified;
+        let mut output = vec![];
+        let read = File::open(&path).and_then(|mut file| {
+            file.seek(SeekFrom::Start(notified.output_offset))?;
+            file.read_to_end(&mut output)
+        });
+        if let Err(e) = read {
+            error!("{}: {e}", path.display());
+            return false;
+        }
+        let Some(end) = output.iter().rposition(|&b| b == b'\n') else {
+            return false;
+        };
+        notified.output_offset += end as u64 + 1;
+        String::from_utf8_lossy(&output[..end])
+            .lines()
+            .any(|line| re.is_match(line))
+    }
+
+    /// Where the output of the next process of an instance starts in its
+    /// stdout file.
+    pub(super) fn output_end(&self, instance: usize) -> u64 {
+        self.output_path(Stream::Stdout, instance)
+            .and_then(|path| fs::metadata(path).ok())
+            .map_or(0, |metadata| metadata.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::backend::process::tests::{wait_for_exit, Instant};
+    use crate::config::structs::{IOHandler, KnownHandler};
+    use crate::config::ProgramConfig;
+
+    #[test]
+    fn process_is_active_once_its_output_matches() {
+        let stdout = std::env::temp_dir().join("taskmaster_ready_stdout");
+        fs::write(&stdout, "Server started from an earlier run\n").unwrap();
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sh");
+        config.args = vec![
+            String::from("-c"),
+            String::from("echo loading; sleep 0.2; echo Server started; sleep 10"),
+        ];
+        config.stdout = IOHandler::FILE(stdout.display().to_string());
+        config.stderr = IOHandler::KNOWN(KnownHandler::DISCARD);
+        config.ready_when =
+            Some(ReadyWhen::StdoutMatches(String::from("^Server started$")));
+        let mut program = Program::build_from((&"api".to_string(), &config));
+        program.create_output_files();
+        program.update_process_count();
+
+        Instant::advance(1);
+        program.update_process_status();
+        assert_eq!(program.processes[0].status, ProcessStatus::Starting);
+
+        thread::sleep(Duration::from_millis(500));
+        program.update_process_status();
+        assert_eq!(program.processes[0].status, ProcessStatus::Active);
+        program.stop_all();
+        wait_for_exit(&mut program.processes[0]);
+        fs::remove_file(stdout).unwrap();
+    }
+
+    #[test]
+    fn process_is_active_once_its_port_accepts_connections() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.stdout = IOHandler::KNOWN(KnownHandler::DISCARD);
+        config.stderr = IOHandler::KNOWN(KnownHandler::DISCARD);
+        config.ready_when = Some(ReadyWhen::PortOpen(port));
+        // other tests move the clock on
+        config.start_timeout = Some(0);
+        let mut program = Program::build_from((&"api".to_string(), &config));
+        program.update_process_count();
+
+        // the connection is looked at on a later pass
+        program.update_process_status();
+        assert_eq!(program.processes[0].status, ProcessStatus::Starting);
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(10));
+            program.update_process_status();
+        }
+
+        assert_eq!(program.processes[0].status, ProcessStatus::Active);
+        program.stop_all();
+        wait_for_exit(&mut program.processes[0]);
+    }
+
+    #[test]
+    fn process_not_ready_in_time_fails_to_start() {
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.ready_when = Some(ReadyWhen::FileExists(String::from("/nonexistent")));
+        config.start_timeout = Some(5);
+        config.retry_start_count = 1;
+        let mut program = Program::build_from((&"api".to_string(), &config));
+        program.update_process_count();
+
+        Instant::advance(6);
+        program.update_process_status();
+        wait_for_exit(&mut program.processes[0]);
+        program.update_process_status();
+        program.update_process_status();
+
+        assert_eq!(program.processes[0].status, ProcessStatus::FailedToStart);
+    }
+
+    #[test]
+    fn process_not_ready_is_not_waited_on_forever_by_default() {
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sleep");
+        config.args = vec![String::from("10")];
+        config.ready_when = Some(ReadyWhen::PortOpen(1));
+        config.retry_start_count = 1;
+        let mut program = Program::build_from((&"api".to_string(), &config));
+        program.update_process_count();
+
+        Instant::advance(31);
+        program.update_process_status();
+        wait_for_exit(&mut program.processes[0]);
+        program.update_process_status();
+        program.update_process_status();
+
+        assert_eq!(program.processes[0].status, ProcessStatus::FailedToStart);
+    }
+}
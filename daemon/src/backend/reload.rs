@@ -0,0 +1,325 @@
+//! Reloading the config file. Programs that were removed are stopped, those
+//! whose command changed are stopped and started again once they stopped,
+//! and other changes are applied to the running programs, starting or
+//! stopping instances for `processes`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use common::Event;
+use logger::{error, info};
+
+use super::print_functions::print_programs;
+use super::{BackEnd, Program};
+use crate::config::{ConfigError, ProgramConfig};
+use crate::watcher::Watcher;
+use crate::TaskMasterConfig;
+
+/// What a reload changes, program by program, sorted by name.
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigDiff {
+    pub added:     Vec<String>,
+    pub removed:   Vec<String>,
+    /// Programs changed in place, with the fields that changed
+    pub changed:   Vec<(String, Vec<String>)>,
+    /// Programs started again for their changes, with the fields that changed
+    pub restarted: Vec<(String, Vec<String>)>,
+    pub unchanged: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn between(old: &TaskMasterConfig, new: &TaskMasterConfig) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+        for (name, config) in &old.programs {
+            let Some(new_config) = new.programs.get(name) else {
+                diff.removed.push(name.clone());
+                continue;
+            };
+            let fields = changed_fields(config, new_config);
+            match (fields.is_empty(), has_major_changes(config, new_config)) {
+                (true, _) => diff.unchanged.push(name.clone()),
+                (false, true) => diff.restarted.push((name.clone(), fields)),
+                (false, false) => diff.changed.push((name.clone(), fields)),
+            }
+        }
+        diff.added = new
+            .programs
+            .keys()
+            .filter(|name| !old.programs.contains_key(*name))
+            .cloned()
+            .collect();
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        diff.restarted.sort();
+        diff.unchanged.sort();
+        diff
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = vec![];
+        for name in &self.added {
+            lines.push(format!("added     {name}"));
+        }
+        for name in &self.removed {
+            lines.push(format!("removed   {name}"));
+        }
+        for (name, fields) in &self.changed {
+            lines.push(format!("changed   {name}: {}", fields.join(", ")));
+        }
+        for (name, fields) in &self.restarted {
+            lines.push(format!("restarted {name}: {}", fields.join(", ")));
+        }
+        for name in &self.unchanged {
+            lines.push(format!("unchanged {name}"));
+        }
+        match lines.is_empty() {
+            true => write!(f, "no programs"),
+            false => write!(f, "{}", lines.join("\n")),
+        }
+    }
+}
+
+/// Names of the fields set differently, as written in the config file.
+fn changed_fields(old: &ProgramConfig, new: &ProgramConfig) -> Vec<String> {
+    let (Ok(old), Ok(new)) = (serde_yaml::to_value(old), serde_yaml::to_value(new))
+    else {
+        return vec![];
+    };
+    let (Some(old), Some(new)) = (old.as_mapping(), new.as_mapping()) else {
+        return vec![];
+    };
+    old.iter()
+        .filter(|(field, value)| new.get(*field) != Some(*value))
+        .filter_map(|(field, _)| field.as_str().map(String::from))
+        .collect()
+}
+
+impl BackEnd {
+    /// Reads and validates the config file the daemon was started with.
+    pub fn read_config(&self) -> Result<TaskMasterConfig, ConfigError> {
+        let file = File::open(&self.config_file)?;
+        let config = TaskMasterConfig::read(file)
+            .map_err(|e| format!("{}: {e}", self.config_file))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Compares the running config with `new_config`, and applies the
+    /// differences unless it is a dry run.
+    pub fn reload_config(
+        &mut self,
+        new_config: TaskMasterConfig,
+        dry_run: bool,
+    ) -> ConfigDiff {
+        let diff = ConfigDiff::between(&self.config, &new_config);
+        if dry_run {
+            return diff;
+        }
+        info!("Updating config\n{diff}");
+        self.update_state(new_config);
+        self.watch_config();
+        self.watch_programs();
+        self.publish(Event::Reload {
+            config: self.config_file.clone(),
+        });
+        diff
+    }
+
+    /// Watches the config file anew while `watch_config` is set, the daemon
+    /// adds the watcher to its epoll set.
+    pub fn watch_config(&mut self) {
+        // closing the last one takes it out of the epoll set
+        self.config_watcher = None;
+        if !self.config.watch_config {
+            return;
+        }
+        let watcher = Watcher::new().and_then(|mut watcher| {
+            watcher.watch(Path::new(&self.config_file))?;
+            Ok(watcher)
+        });
+        match watcher {
+            Ok(watcher) => {
+                self.config_watcher = Some(watcher);
+                self.watchers_changed = true;
+            }
+            Err(e) => error!("{} not watched: {e}", self.config_file),
+        }
+    }
+
+    fn update_state(&mut self, new_config: TaskMasterConfig) {
+        let programs_to_kill = get_diff(&self.config.programs, &new_config.programs);
+        print_programs("programs to kill", &programs_to_kill);
+        let programs_to_spawn = get_diff(&new_config.programs, &self.config.programs);
+        print_programs("programs to spawn", &programs_to_spawn);
+
+        for name in programs_to_kill.keys() {
+            if let Some(mut program) = self.programs.remove(name) {
+                program.stop_all();
+                self.retiring.push(program);
+            }
+        }
+        for name in new_config.start_order() {
+            let config = &new_config.programs[&name];
+            if programs_to_spawn.contains_key(&name) {
+                let mut program = Program::build_from((&name, config));
+                program.create_output_files();
+                // the sockets are taken over, the connections waiting on them
+                // are not refused
+                let mut previous = self
+                    .retiring
+                    .iter_mut()
+                    .rfind(|program| program.config_name == name)
+                    .map(|program| std::mem::take(&mut program.sockets))
+                    .unwrap_or_default();
+                program.open_sockets(&mut previous);
+                // a replaced program starts once the old one stopped
+                if config.run_at_startup && !self.is_retiring(&name) {
+                    program.update_process_count();
+                }
+                self.programs.insert(name, program);
+            } else if let Some(program) = self.programs.get_mut(&name) {
+                program.config = config.clone();
+                program.create_output_files();
+                // one that was not started stays so
+                if !program.processes.is_empty() {
+                    program.update_process_count();
+                }
+            }
+        }
+        self.config = new_config;
+    }
+
+    /// Drops the retiring programs that stopped, starting the programs that
+    /// replace them.
+    pub(super) fn update_retiring_programs(&mut self) {
+        for program in self.retiring.iter_mut() {
+            program.update_process_status();
+        }
+        let (stopped, retiring) = self
+            .retiring
+            .drain(..)
+            .partition::<Vec<Program>, _>(Program::is_stopped);
+        self.retiring = retiring;
+        for name in stopped.iter().map(|program| &program.config_name) {
+            if self.is_retiring(name) {
+                continue;
+            }
+            if let Some(program) = self.programs.get_mut(name) {
+                if program.config.run_at_startup && program.processes.is_empty() {
+                    program.update_process_count();
+                }
+            }
+        }
+    }
+
+    fn is_retiring(&self, name: &str) -> bool {
+        self.retiring
+            .iter()
+            .any(|program| program.config_name == name)
+    }
+}
+
+fn get_diff(
+    first_list: &HashMap<String, ProgramConfig>,
+    second_list: &HashMap<String, ProgramConfig>,
+) -> HashMap<String, ProgramConfig> {
+    first_list
+        .iter()
+        .filter(|&(key_in_first, config)| {
+            !second_list.contains_key(key_in_first)
+                || has_major_changes(config, &second_list[key_in_first])
+        })
+        .map(|(key, program)| (key.to_owned(), program.clone()))
+        .collect()
+}
+
+/// Changes to what a process is spawned with, it has to be started again.
+fn has_major_changes(first: &ProgramConfig, second: &ProgramConfig) -> bool {
+    first.command != second.command
+        || first.args != second.args
+        // || first.status != second.status
+        // || first.processes != second.processes
+        // || first.run_at_startup != second.run_at_startup
+        // || first.retry_start_count != second.retry_start_count
+        // || first.restart != second.restart
+        // || first.graceful_exit != second.graceful_exit
+        // || first.ttk != second.ttk
+        // || first.success_codes != second.success_codes.clone()
+        // || first.succesful_start_after != second.succesful_start_after
+        || first.workdir != second.workdir
+        || first.environment_variables != second.environment_variables
+        || first.umask != second.umask
+        || first.logdir != second.logdir
+        || first.stdin != second.stdin
+        || first.stdout != second.stdout
+        || first.stderr != second.stderr
+        || first.allow_stdin != second.allow_stdin
+        || first.tty != second.tty
+        || first.event_listener != second.event_listener
+        || first.ready != second.ready
+        || first.watchdog != second.watchdog
+        || first.sockets != second.sockets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> TaskMasterConfig { serde_yaml::from_str(yaml).unwrap() }
+
+    #[test]
+    fn diff_tells_which_fields_changed() {
+        let old = config(
+            "programs:
+                web: {command: nginx, processes: 2}
+                api: {command: api}
+                db: {command: postgres}
+                old: {command: legacy}",
+        );
+        let new = config(
+            "programs:
+                web: {command: nginx, processes: 4, ttk: 3}
+                api: {command: api, args: ['--port', '80']}
+                db: {command: postgres}
+                cache: {command: redis}",
+        );
+
+        let diff = ConfigDiff::between(&old, &new);
+
+        assert_eq!(diff.added, ["cache"]);
+        assert_eq!(diff.removed, ["old"]);
+        assert_eq!(diff.unchanged, ["db"]);
+        let fields = |list: &[(String, Vec<String>)]| list[0].1.clone();
+        assert_eq!(fields(&diff.restarted), ["args"]);
+        assert_eq!(fields(&diff.changed), ["processes", "ttk"]);
+        assert!(diff.to_string().contains("restarted api: args"));
+    }
+
+    #[test]
+    fn reloads_watch_the_programs_they_add_and_drop_those_they_remove() {
+        let dir = std::env::temp_dir();
+        let dir = dir.display();
+        let mut backend = BackEnd::new(config(&format!(
+            "programs:
+                old: {{command: legacy, run_at_startup: false, watch: ['{dir}']}}"
+        )));
+
+        backend.reload_config(
+            config(&format!(
+                "programs:
+                    web: {{command: nginx, run_at_startup: false, stdout: DISCARD,
+                          stderr: DISCARD, watch: ['{dir}']}}"
+            )),
+            false,
+        );
+
+        assert!(backend.watcher.is_some() && backend.watchers_changed);
+        let watching: Vec<_> = backend.watched.values().flatten().collect();
+        assert_eq!(watching, ["web"]);
+    }
+}
@@ -0,0 +1,181 @@
+//! Restarting the instances of a program a batch at a time. The instances of
+//! a batch are stopped through their stop ladder and started again, the next
+//! batch waits until they are active, so the others keep serving meanwhile.
+//! A batch of every instance restarts them all at once.
+
+use std::collections::VecDeque;
+
+use logger::{info, warning};
+
+use super::{ProcessStatus, Program};
+
+pub struct RollingRestart {
+    /// Instances left to restart, in order
+    pending: VecDeque<usize>,
+    /// Instances of the current batch, and whether they were started again
+    batch:   Vec<(usize, bool)>,
+    size:    usize,
+}
+
+impl RollingRestart {
+    /// Instances not restarted yet, those of the current batch included.
+    pub fn remaining(&self) -> usize { self.pending.len() + self.batch.len() }
+}
+
+impl Program {
+    /// Restarts every instance, `batch` at a time, starting over if a
+    /// restart was going on.
+    pub fn restart_instances(&mut self, batch: usize) {
+        info!(
+            "{}: restarting {} instances, {} at a time",
+            self.config_name,
+            self.processes.len(),
+            batch.max(1)
+        );
+        self.restarting = Some(RollingRestart {
+            pending: (0..self.processes.len()).collect(),
+            batch:   vec![],
+            size:    batch.max(1),
+        });
+        self.advance_restart();
+    }
+
+    /// Starts again the stopped instances of the batch, and the next batch
+    /// once they are all active. A failed start ends the restart, the
+    /// instances left keep their processes.
+    pub(super) fn advance_restart(&mut self) {
+        let Some(mut restart) = self.restarting.take() else {
+            return;
+        };
+        loop {
+            if restart.batch.is_empty() {
+                let count = restart.size.min(restart.pending.len());
+                restart.batch = restart
+                    .pending
+                    .drain(..count)
+                    .filter(|&i| i < self.processes.len())
+                    .map(|i| (i, false))
+                    .collect();
+                if count == 0 {
+                    info!("{}: restarted", self.config_name);
+                    return;
+                }
+                for &(i, _) in &restart.batch {
+                    self.processes[i].should_restart = false;
+                    self.processes[i].should_try_again = false;
+                    self.stop(i);
+                }
+            }
+            restart.batch.retain(|&(i, _)| i < self.processes.len());
+            for (i, started) in restart.batch.iter_mut() {
+                let process = &self.processes[*i];
+                // its post_stop hook ends before it starts again
+                if !*started && !process.is_running() && process.hooks.is_empty() {
+                    self.respawn(*i);
+                    *started = true;
+                }
+            }
+            let failed = restart.batch.iter().find(|&&(i, started)| {
+                let status = self.processes[i].status;
+                started
+                    && (status == ProcessStatus::FailedToStart || status.has_exited())
+            });
+            if let Some((i, _)) = failed {
+                warning!("{}[{i}] did not start, restart stopped", self.config_name);
+                return;
+            }
+            let active = restart.batch.iter().all(|&(i, started)| {
+                started && self.processes[i].status == ProcessStatus::Active
+            });
+            if !active {
+                break;
+            }
+            restart.batch.clear();
+        }
+        self.restarting = Some(restart);
+    }
+
+    /// Replaces an instance by a new process.
+    fn respawn(&mut self, instance: usize) {
+        let process = self.spawn(instance);
+        self.replace(instance, process);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::process::tests::{wait_for_exit, Instant};
+    use crate::config::ProgramConfig;
+
+    #[test]
+    fn instances_are_restarted_a_batch_at_a_time() {
+        let config: ProgramConfig =
+            serde_yaml::from_str("{command: sleep, args: ['10'], processes: 3}")
+                .unwrap();
+        let mut program = Program::build_from((&"web".to_string(), &config));
+        program.update_process_count();
+        Instant::advance(1);
+        program.update_process_status();
+        let pids = |program: &Program| -> Vec<_> {
+            program.processes.iter().map(|p| p.pid()).collect()
+        };
+        let old = pids(&program);
+
+        program.restart_instances(2);
+        let stopping = |program: &Program| -> Vec<_> {
+            program
+                .processes
+                .iter()
+                .map(|p| p.status == ProcessStatus::Stopping)
+                .collect()
+        };
+        assert_eq!(stopping(&program), [true, true, false]);
+
+        wait_for_exit(&mut program.processes[0]);
+        wait_for_exit(&mut program.processes[1]);
+        program.update_process_status();
+        let new = pids(&program);
+        assert!(new[0] != old[0] && new[1] != old[1] && new[2] == old[2]);
+
+        // the last one is stopped once the new ones are active
+        Instant::advance(1);
+        program.update_process_status();
+        assert_eq!(stopping(&program), [false, false, true]);
+
+        wait_for_exit(&mut program.processes[2]);
+        program.update_process_status();
+        Instant::advance(1);
+        program.update_process_status();
+        assert!(program.restarting.is_none());
+        program.stop_all();
+        for process in program.processes.iter_mut() {
+            wait_for_exit(process);
+        }
+    }
+
+    #[test]
+    fn a_restart_stops_at_the_first_instance_failing_to_start() {
+        let config: ProgramConfig = serde_yaml::from_str(
+            "{command: sleep, args: ['10'], processes: 2, retry_start_count: 1}",
+        )
+        .unwrap();
+        let mut program = Program::build_from((&"web".to_string(), &config));
+        program.update_process_count();
+        Instant::advance(1);
+        program.update_process_status();
+
+        program.command = std::process::Command::new("/nonexistent/web");
+        program.restart_instances(1);
+        wait_for_exit(&mut program.processes[0]);
+        for _ in 0..3 {
+            program.update_process_status();
+        }
+
+        assert_eq!(program.processes[0].status, ProcessStatus::FailedToStart);
+        assert_eq!(program.processes[1].status, ProcessStatus::Active);
+        assert!(program.restarting.is_none());
+        program.stop_all();
+        wait_for_exit(&mut program.processes[1]);
+    }
+}
@@ -0,0 +1,146 @@
+//! Stopping every program before the daemon exits. Programs are all stopped
+//! at once, each of their processes through its stop ladder, unless the
+//! daemon is told again to exit and kills them.
+
+use common::Event;
+use logger::{info, warning};
+
+use super::{BackEnd, Program};
+
+impl BackEnd {
+    /// Starts shutting down, the programs are stopped by `stop_programs`.
+    pub fn shutdown(&mut self) {
+        info!("shutting down");
+        self.publish(Event::Shutdown);
+        self.stop_programs();
+    }
+
+    /// Stops every program, true once they all stopped.
+    pub fn stop_programs(&mut self) -> bool {
+        for program in self.programs.values_mut() {
+            program.stop_all();
+        }
+        self.programs.values().all(Program::is_stopped) && self.retiring.is_empty()
+    }
+
+    /// Kills every process still running and their hooks, for the daemon to
+    /// exit without waiting on their stop ladders.
+    pub fn kill_programs(&mut self) {
+        warning!("killing the processes still running");
+        for program in self.programs.values_mut().chain(self.retiring.iter_mut()) {
+            program.kill_all();
+        }
+    }
+
+    /// How many processes had to be killed, having outlived their stop
+    /// ladder.
+    pub fn killed_on_stop(&self) -> usize {
+        self.programs.values().map(Program::killed_on_stop).sum()
+    }
+}
+
+impl Program {
+    /// Stops every instance, highest first, for none to be started again.
+    pub fn stop_all(&mut self) {
+        self.restarting = None;
+        for i in (0..self.processes.len()).rev() {
+            self.processes[i].should_restart = false;
+            self.processes[i].should_try_again = false;
+            self.stop(i);
+        }
+    }
+
+    /// Kills every instance and their hooks, for none to be started again.
+    pub fn kill_all(&mut self) {
+        self.stop_all();
+        for process in self.processes.iter_mut() {
+            for hook in process.hooks.iter_mut() {
+                hook.kill();
+            }
+            if process.is_running() {
+                process
+                    .kill(&self.config)
+                    .unwrap_or_else(|e| warning!("{}: {e}", self.config_name));
+            }
+        }
+    }
+
+    /// Whether no instance is running, nor any of their hooks.
+    pub fn is_stopped(&self) -> bool {
+        self.processes
+            .iter()
+            .all(|p| !p.is_running() && p.hooks.is_empty())
+    }
+
+    fn killed_on_stop(&self) -> usize {
+        let ladder = self.config.stop_ladder().len();
+        let killed = self
+            .processes
+            .iter()
+            .filter_map(|process| process.last_exit()?.stop_step)
+            .filter(|step| step.index >= ladder)
+            .count();
+        if killed > 0 {
+            warning!("{}: {killed} processes killed", self.config_name);
+        }
+        killed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::process::tests::wait_for_exit;
+    use crate::backend::ProcessStatus;
+    use crate::TaskMasterConfig;
+
+    fn backend(yaml: &str) -> BackEnd {
+        let config: TaskMasterConfig = serde_yaml::from_str(yaml).unwrap();
+        let mut backend = BackEnd::default();
+        for (name, config) in &config.programs {
+            let mut program = Program::build_from((name, config));
+            program.update_process_count();
+            backend.programs.insert(name.clone(), program);
+        }
+        backend.config = config;
+        backend
+    }
+
+    #[test]
+    fn programs_are_all_stopped_at_once() {
+        let mut backend = backend(
+            "programs:
+                db: {command: sleep, args: ['10']}
+                web: {command: sleep, args: ['10']}",
+        );
+
+        backend.shutdown();
+        for program in backend.programs.values() {
+            assert_eq!(program.processes[0].status, ProcessStatus::Stopping);
+        }
+
+        for program in backend.programs.values_mut() {
+            wait_for_exit(&mut program.processes[0]);
+            program.update_process_status();
+        }
+        assert!(backend.stop_programs());
+        assert_eq!(backend.killed_on_stop(), 0);
+    }
+
+    #[test]
+    fn programs_killed_on_a_second_shutdown_count_as_killed() {
+        let mut backend = backend(
+            "programs:
+                web: {command: sleep, args: ['10']}",
+        );
+
+        backend.shutdown();
+        backend.kill_programs();
+
+        let program = backend.programs.get_mut("web").unwrap();
+        wait_for_exit(&mut program.processes[0]);
+        program.update_process_status();
+        assert!(backend.stop_programs());
+        assert_eq!(backend.killed_on_stop(), 1);
+    }
+}
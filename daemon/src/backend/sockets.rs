@@ -0,0 +1,166 @@
+//! Socket activation, as systemd's: the daemon binds the `sockets` of a
+//! program once and every instance finds them from descriptor 3 on, told by
+//! LISTEN_FDS how many there are, by LISTEN_FDNAMES their names and by
+//! LISTEN_PID that they are meant for it. The sockets outlive the processes,
+//! a restarted instance takes over the connections waiting on them, and are
+//! handed to the program replacing this one on reload.
+
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::{fs, io};
+
+use common::syscall;
+use logger::error;
+
+use super::Program;
+use crate::config::SocketConfig;
+
+/// First descriptor of the passed sockets, SD_LISTEN_FDS_START.
+const LISTEN_FDS_START: RawFd = 3;
+
+pub struct ListeningSocket {
+    pub listen: String,
+    pub name:   String,
+    fd:         OwnedFd,
+    unix:       bool,
+}
+
+impl ListeningSocket {
+    /// Listens on a Unix socket, replacing one left over by an earlier
+    /// daemon, or on a TCP address.
+    fn bind(config: &SocketConfig, name: String) -> io::Result<ListeningSocket> {
+        let fd = match config.is_unix() {
+            true => {
+                let path = Path::new(&config.listen);
+                if fs::metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    fs::remove_file(path)?;
+                }
+                OwnedFd::from(UnixListener::bind(path)?)
+            }
+            false => OwnedFd::from(TcpListener::bind(&config.listen)?),
+        };
+        Ok(ListeningSocket {
+            listen: config.listen.clone(),
+            name,
+            fd,
+            unix: config.is_unix(),
+        })
+    }
+}
+
+impl AsRawFd for ListeningSocket {
+    fn as_raw_fd(&self) -> RawFd { self.fd.as_raw_fd() }
+}
+
+impl Drop for ListeningSocket {
+    fn drop(&mut self) {
+        if self.unix {
+            fs::remove_file(&self.listen).unwrap_or_default();
+        }
+    }
+}
+
+impl Program {
+    /// Listens on the `sockets` of the program for its instances to inherit,
+    /// taking over those of `previous` listening on the same address.
+    pub fn open_sockets(&mut self, previous: &mut Vec<ListeningSocket>) {
+        for config in &self.config.sockets {
+            let name = config.name.clone().unwrap_or(self.config_name.clone());
+            let socket = match previous.iter().position(|s| s.listen == config.listen) {
+                Some(i) => {
+                    let mut socket = previous.swap_remove(i);
+                    socket.name = name;
+                    Ok(socket)
+                }
+                None => ListeningSocket::bind(config, name),
+            };
+            match socket {
+                Ok(socket) => self.sockets.push(socket),
+                Err(e) => error!("{}: {}: {e}", self.config_name, config.listen),
+            }
+        }
+        if self.sockets.is_empty() {
+            return;
+        }
+        let names: Vec<&str> = self.sockets.iter().map(|s| s.name.as_str()).collect();
+        self.command
+            .env("LISTEN_FDS", self.sockets.len().to_string())
+            .env("LISTEN_FDNAMES", names.join(":"));
+        let fds: Vec<RawFd> = self.sockets.iter().map(AsRawFd::as_raw_fd).collect();
+        let mut copies = vec![0; fds.len()];
+        unsafe {
+            self.command
+                .pre_exec(move || pass_sockets(&fds, &mut copies))
+        };
+    }
+}
+
+/// Moves the sockets to the descriptors from 3 on in the forked child,
+/// through copies above them for none to be overwritten before it moved.
+/// `copies` is given for nothing to be allocated after the fork.
+fn pass_sockets(fds: &[RawFd], copies: &mut [RawFd]) -> io::Result<()> {
+    let above = LISTEN_FDS_START + fds.len() as RawFd;
+    for (fd, copy) in fds.iter().zip(copies.iter_mut()) {
+        *copy = syscall!(fcntl(*fd, libc::F_DUPFD_CLOEXEC, above))?;
+    }
+    // the duplicates are inherited, the copies are closed on exec
+    for (target, copy) in (LISTEN_FDS_START..).zip(copies.iter()) {
+        syscall!(dup2(*copy, target))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::process::tests::wait_for_exit;
+    use crate::config::ProgramConfig;
+
+    #[test]
+    fn instances_inherit_the_sockets_from_descriptor_3() {
+        let dir = std::env::temp_dir().join("taskmaster_sockets");
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("output");
+        let mut config = ProgramConfig::new();
+        config.command = String::from("sh");
+        config.args = vec![
+            String::from("-c"),
+            format!(
+                "echo $LISTEN_FDS $LISTEN_FDNAMES $((LISTEN_PID - $$)) $(readlink \
+                 /proc/$$/fd/3 | cut -c-7) > {}",
+                output.display()
+            ),
+        ];
+        let admin = dir.join("admin.sock");
+        config.sockets = vec![
+            SocketConfig {
+                listen: String::from("127.0.0.1:0"),
+                name:   None,
+            },
+            SocketConfig {
+                listen: admin.display().to_string(),
+                name:   Some(String::from("admin")),
+            },
+        ];
+        let mut program = Program::build_from((&"web".to_string(), &config));
+        program.open_sockets(&mut vec![]);
+        program.update_process_count();
+        wait_for_exit(&mut program.processes[0]);
+
+        let output = fs::read_to_string(&output).unwrap();
+        assert_eq!(output, "2 web:admin 0 socket:\n");
+
+        // another program takes them over instead of binding them again
+        let mut next = Program::build_from((&"web".to_string(), &config));
+        next.open_sockets(&mut program.sockets);
+        assert_eq!(next.sockets.len(), 2);
+        assert!(UnixListener::bind(&admin).is_err());
+        drop(next);
+        assert!(!admin.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use common::{CmdError, CmdErrorKind};
+
+use super::print_functions::{format_duration, format_time};
+use super::{BackEnd, Process, Program};
+
+impl BackEnd {
+    /// Status report for the STATUS command. A single program name gives the
+    /// detailed view of its instances, otherwise every argument filters the
+    /// summary by program or group name.
+    pub fn format_status(&self, filters: &[String]) -> Result<String, CmdError> {
+        if let [name] = filters {
+            if let Some(program) = self.programs.get(name) {
+                return Ok(detailed_status(program));
+            }
+        }
+        for filter in filters {
+            if !self.programs.contains_key(filter) && !self.is_group(filter) {
+                return Err(CmdErrorKind::NotFound(filter.clone()).into());
+            }
+        }
+
+        let mut programs = self
+            .programs
+            .values()
+            .filter(|p| filters.is_empty() || filters.iter().any(|f| matches(p, f)))
+            .collect::<Vec<_>>();
+        programs.sort_by(|a, b| a.config_name.cmp(&b.config_name));
+
+        let mut dump = String::new();
+        for program in programs {
+            for (i, process) in program.processes.iter().enumerate() {
+                dump.push_str(&format!(
+                    "{:15}[{}]: {}\n",
+                    program.config_name,
+                    i,
+                    summary(process)
+                ));
+            }
+        }
+        dump.pop();
+        Ok(dump)
+    }
+
+    pub(super) fn is_group(&self, name: &str) -> bool {
+        self.programs
+            .values()
+            .any(|p| p.config.group.as_deref() == Some(name))
+    }
+}
+
+fn matches(program: &Program, filter: &str) -> bool {
+    program.config_name == filter || program.config.group.as_deref() == Some(filter)
+}
+
+fn summary(process: &Process) -> String {
+    let pid = process.pid().map_or("-".into(), |pid| pid.to_string());
+    let uptime = process.uptime().map_or("-".into(), format_duration);
+    format!(
+        "{:16} pid {:8} uptime {:12} restarts {}",
+        process.status, pid, uptime, process.restart_count
+    )
+}
+
+fn detailed_status(program: &Program) -> String {
+    let config = &program.config;
+    let mut dump = format!("{}\n", program.config_name);
+    if !config.description.is_empty() {
+        dump.push_str(&format!("  description: {}\n", config.description));
+    }
+    let command = std::iter::once(&config.command)
+        .chain(&config.args)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" ");
+    dump.push_str(&format!("  command:     {command}\n"));
+    if let Some(group) = &config.group {
+        dump.push_str(&format!("  group:       {group}\n"));
+    }
+    if let Some(restart) = &program.restarting {
+        dump.push_str(&format!(
+            "  restarting:  {} processes left\n",
+            restart.remaining()
+        ));
+    }
+    for (i, process) in program.processes.iter().enumerate() {
+        dump.push_str(&format!("  [{i}] {}\n", process.status));
+        if let Some(pid) = process.pid() {
+            dump.push_str(&format!("      pid:       {pid}\n"));
+        }
+        if let Some(uptime) = process.uptime() {
+            dump.push_str(&format!("      uptime:    {}\n", format_duration(uptime)));
+        }
+        if let Some(start_time) = process.start_time {
+            dump.push_str(&format!("      started:   {}\n", format_time(start_time)));
+        }
+        if let Some(status) = &process.notified.status {
+            dump.push_str(&format!("      says:      {status}\n"));
+        }
+        dump.push_str(&format!("      restarts:  {}\n", process.restart_count));
+        dump.push_str(&format!(
+            "      tries:     {}/{}\n",
+            process.try_count, config.retry_start_count
+        ));
+        if let Some(exit) = process.last_exit() {
+            dump.push_str(&format!(
+                "      last exit: {} at {}\n",
+                exit.status,
+                format_time(exit.at)
+            ));
+            if let Some(step) = exit.stop_step {
+                dump.push_str(&format!("      stopped:   {step}\n"));
+            }
+        }
+    }
+    dump.pop();
+    dump
+}
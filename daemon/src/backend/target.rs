@@ -0,0 +1,41 @@
+use std::fmt;
+use std::str::FromStr;
+
+use common::CmdError;
+
+/// A `program[:instance]` reference as typed by a client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub program:  String,
+    pub instance: Option<usize>,
+}
+
+impl FromStr for Target {
+    type Err = CmdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (program, instance) = match s.split_once(':') {
+            Some((program, instance)) => match instance.parse::<usize>() {
+                Ok(instance) => (program, Some(instance)),
+                Err(_) => return Err(format!("invalid instance: {instance}").into()),
+            },
+            None => (s, None),
+        };
+        if program.is_empty() {
+            return Err("missing program name".into());
+        }
+        Ok(Target {
+            program: program.into(),
+            instance,
+        })
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.instance {
+            Some(instance) => write!(f, "{}:{}", self.program, instance),
+            None => write!(f, "{}", self.program),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Restarting programs when the files they `watch` change, for a program to
+//! follow its binary or sources while it is developed. The changes are
+//! debounced as builds and editors write several files in a row.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use logger::{debug, error, info};
+
+use super::{BackEnd, ProcessStatus};
+use crate::watcher::Watcher;
+
+/// How long the watched files of a program must stay unchanged before it is
+/// restarted.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+impl BackEnd {
+    /// Watches the `watch` paths of every program anew, the daemon adds the
+    /// watcher to its epoll set. None is left if no program watches anything.
+    pub fn watch_programs(&mut self) {
+        // closing the last one takes it out of the epoll set
+        self.watcher = None;
+        self.watched.clear();
+        let programs = &self.programs;
+        self.changed.retain(|name, _| programs.contains_key(name));
+        let mut watcher = None;
+        for (name, program) in &self.programs {
+            for path in &program.config.watch {
+                let path = Path::new(&program.config.workdir).join(path);
+                if watcher.is_none() {
+                    watcher = Watcher::new()
+                        .map_err(|e| error!("programs not watched: {e}"))
+                        .ok();
+                }
+                let Some(watcher) = watcher.as_mut() else {
+                    return;
+                };
+                match watcher.watch_tree(&path) {
+                    Ok(()) => self.watched.entry(path).or_default().push(name.clone()),
+                    Err(e) => error!("{name}: {} not watched: {e}", path.display()),
+                }
+            }
+        }
+        if watcher.is_some() {
+            self.watcher = watcher;
+            self.watchers_changed = true;
+        }
+    }
+
+    /// Notes which programs the watched paths that changed belong to.
+    pub fn handle_watched_changes(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+        let changed = match watcher.read() {
+            Ok(changed) => changed,
+            Err(e) => {
+                error!("watched programs: {e}");
+                return;
+            }
+        };
+        for path in changed {
+            debug!("{} changed", path.display());
+            for name in self.watched.get(&path).into_iter().flatten() {
+                self.changed.insert(name.clone(), Instant::now());
+            }
+        }
+    }
+
+    /// Restarts the programs whose watched files settled, one instance at a
+    /// time with `watch_rolling`. Those that were never started or were
+    /// stopped stay so, those that failed are given another start.
+    pub(super) fn restart_changed_programs(&mut self) {
+        let settled: Vec<String> = self
+            .changed
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= WATCH_DEBOUNCE)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in settled {
+            self.changed.remove(&name);
+            let Some(program) = self.programs.get_mut(&name) else {
+                continue;
+            };
+            let stopped = program
+                .processes
+                .iter()
+                .all(|process| process.status == ProcessStatus::Stopped);
+            if stopped {
+                continue;
+            }
+            info!("{name}: watched files changed");
+            let batch = match program.config.watch_rolling {
+                true => 1,
+                false => program.processes.len(),
+            };
+            program.restart_instances(batch);
+        }
+    }
+}
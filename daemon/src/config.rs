@@ -3,15 +3,28 @@ use std::fs::File;
 
 use serde::{Deserialize, Serialize};
 
+pub mod edit;
 pub mod error;
 pub mod exceptions;
 pub mod structs;
 pub use error::ConfigError;
-pub use structs::{ProgramConfig, RestartOption, Signal};
+pub use structs::{
+    ProgramConfig,
+    ReadyOption,
+    ReadyWhen,
+    RestartOption,
+    Signal,
+    SignalError,
+    SocketConfig,
+    StopSignal,
+};
 
 #[derive(Default, Debug, Serialize, Deserialize, PartialEq)]
 pub struct TaskMasterConfig {
-    pub programs: HashMap<String, ProgramConfig>,
+    pub programs:     HashMap<String, ProgramConfig>,
+    /// Reload the config whenever its file changes
+    #[serde(default)]
+    pub watch_config: bool,
 }
 
 impl TaskMasterConfig {
@@ -25,6 +38,13 @@ impl TaskMasterConfig {
         }
         Ok(())
     }
+
+    /// Names of the programs in the order they are started.
+    pub fn start_order(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.programs.keys().cloned().collect();
+        names.sort();
+        names
+    }
 }
 
 impl From<File> for TaskMasterConfig {
@@ -0,0 +1,107 @@
+//! Changes made to the config file at runtime. The file is edited line by line
+//! rather than written back from the parsed config, to keep its comments and
+//! layout, and only replaced if it reads back as intended.
+
+use std::fs;
+
+use super::{ConfigError, TaskMasterConfig};
+
+/// Sets the `processes` of a program in a config file.
+pub fn save_process_count(
+    path: &str,
+    program: &str,
+    count: usize,
+) -> Result<(), ConfigError> {
+    let text = fs::read_to_string(path)?;
+    let edited = set_process_count(&text, program, count)
+        .ok_or(format!("{path}: no block for {program}, edit it by hand"))?;
+    let config: TaskMasterConfig = serde_yaml::from_str(&edited)
+        .map_err(|e| format!("{path}: edit would break the config: {e}"))?;
+    if config.programs.get(program).map(|p| p.processes) != Some(count) {
+        return Err(
+            format!("{path}: {program} could not be edited, edit it by hand").into()
+        );
+    }
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, edited)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn set_process_count(text: &str, program: &str, count: usize) -> Option<String> {
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let is_content = |line: &str| {
+        let trimmed = line.trim_start();
+        !trimmed.is_empty() && !trimmed.starts_with('#')
+    };
+
+    let programs = lines
+        .iter()
+        .position(|line| line.trim_end() == "programs:")?;
+    let key = format!("{program}:");
+    let is_key = |line: &String| {
+        let rest = line.trim_start().strip_prefix(key.as_str());
+        indent(line) > 0 && rest.is_some_and(|rest| !is_content(rest))
+    };
+    let start = programs + 1 + lines[programs + 1..].iter().position(is_key)?;
+    let program_indent = indent(&lines[start]);
+    let block_end = lines[start + 1..]
+        .iter()
+        .position(|line| is_content(line) && indent(line) <= program_indent)
+        .map_or(lines.len(), |end| start + 1 + end);
+    let field_indent = lines[start + 1..block_end]
+        .iter()
+        .find(|line| is_content(line))
+        .map_or(program_indent + 4, |line| indent(line));
+
+    let field = format!("{}processes: {count}", " ".repeat(field_indent));
+    match lines[start + 1..block_end].iter().position(|line| {
+        indent(line) == field_indent && line.trim_start().starts_with("processes:")
+    }) {
+        Some(i) => {
+            let line = &mut lines[start + 1 + i];
+            let comment = line.find(" #").map(|at| line[at..].to_string());
+            *line = field + comment.as_deref().unwrap_or_default();
+        }
+        None => lines.insert(start + 1, field),
+    }
+    let mut edited = lines.join("\n");
+    if text.ends_with('\n') {
+        edited.push('\n');
+    }
+    Some(edited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "programs:
+    web:
+        command: \"nginx\"
+        processes: 2 # one per core
+    # worker:
+    #     processes: 9
+    worker:
+        command: \"sleep\"
+";
+
+    #[test]
+    fn process_count_is_replaced_in_place() {
+        let edited = set_process_count(CONFIG, "web", 4).unwrap();
+
+        assert_eq!(edited, CONFIG.replace("processes: 2", "processes: 4"));
+    }
+
+    #[test]
+    fn process_count_is_added_when_missing() {
+        let edited = set_process_count(CONFIG, "worker", 3).unwrap();
+
+        assert!(edited.ends_with(
+            "    worker:\n        processes: 3\n        command: \"sleep\"\n"
+        ));
+        assert!(edited.contains("#     processes: 9"));
+        assert_eq!(set_process_count(CONFIG, "db", 1), None);
+    }
+}
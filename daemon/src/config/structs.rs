@@ -1,9 +1,13 @@
 mod file_handler;
 mod program;
+mod ready_option;
 mod restart_option;
 mod signals;
+mod socket_config;
 
 pub use file_handler::{IOHandler, KnownHandler};
 pub use program::ProgramConfig;
+pub use ready_option::{ReadyOption, ReadyWhen};
 pub use restart_option::RestartOption;
-pub use signals::Signal;
+pub use signals::{Signal, SignalError, StopSignal};
+pub use socket_config::SocketConfig;
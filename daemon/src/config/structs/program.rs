@@ -1,12 +1,25 @@
 use std::fs;
 
 use logger::debug;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
 use super::file_handler::KnownHandler;
-use super::{IOHandler, RestartOption, Signal};
+use super::{
+    IOHandler,
+    ReadyOption,
+    ReadyWhen,
+    RestartOption,
+    Signal,
+    SocketConfig,
+    StopSignal,
+};
 use crate::config::ConfigError;
 
+/// Seconds a process with `ready_when` has to get ready unless
+/// `start_timeout` is given.
+const DEFAULT_START_TIMEOUT: u32 = 30;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 pub struct ProgramConfig {
@@ -27,6 +40,33 @@ pub struct ProgramConfig {
     pub stdin:                 IOHandler,
     pub stdout:                IOHandler,
     pub stderr:                IOHandler,
+    pub allow_stdin:           bool,
+    pub tty:                   bool,
+    pub description:           String,
+    pub group:                 Option<String>,
+    pub event_listener:        bool,
+    pub event_filter:          Vec<String>,
+    pub pre_start:             Option<String>,
+    pub post_start:            Option<String>,
+    pub pre_stop:              Option<String>,
+    pub post_stop:             Option<String>,
+    pub hook_timeout:          u32, // seconds
+    pub stop_command:          Option<String>,
+    pub stop_signals:          Vec<StopSignal>,
+    /// Files and directories whose changes restart the program, relative to
+    /// its workdir
+    pub watch:                 Vec<String>,
+    /// Whether those restarts go one instance at a time
+    pub watch_rolling:         bool,
+    pub ready:                 ReadyOption,
+    /// Seconds a notifying process may go without a WATCHDOG=1 before it is
+    /// aborted, 0 for no watchdog
+    pub watchdog:              u32,
+    pub ready_when:            Option<ReadyWhen>,
+    /// Seconds a process waited on to be ready has to get so, or its start
+    /// failed, 0 to wait for as long as it takes
+    pub start_timeout:         Option<u32>,
+    pub sockets:               Vec<SocketConfig>,
 }
 
 impl ProgramConfig {
@@ -41,6 +81,53 @@ impl ProgramConfig {
                 }
             }
         }
+        if (self.allow_stdin || self.tty)
+            && self.stdin != IOHandler::KNOWN(KnownHandler::DEFAULT)
+        {
+            return Err(
+                "stdin handler cannot be combined with allow_stdin or tty".into()
+            );
+        }
+        // the listener protocol runs over stdin and stdout
+        if self.event_listener
+            && (self.allow_stdin
+                || self.tty
+                || self.stdin != IOHandler::KNOWN(KnownHandler::DEFAULT)
+                || self.stdout != IOHandler::KNOWN(KnownHandler::DEFAULT))
+        {
+            return Err("event_listener owns stdin and stdout".into());
+        }
+        // heartbeats come through the notify socket
+        if self.watchdog > 0 && self.ready != ReadyOption::Notify {
+            return Err("watchdog needs ready: notify".into());
+        }
+        match &self.ready_when {
+            Some(_) if self.ready == ReadyOption::Notify => {
+                return Err("ready_when cannot be combined with ready: notify".into());
+            }
+            Some(ReadyWhen::StdoutMatches(pattern)) => {
+                Regex::new(pattern).map_err(|e| format!("stdout_matches: {e}"))?;
+                if self.stdout == IOHandler::KNOWN(KnownHandler::DISCARD)
+                    || self.event_listener
+                {
+                    return Err("stdout_matches needs stdout written to a file".into());
+                }
+            }
+            _ => {}
+        }
+        for socket in &self.sockets {
+            if socket.listen.is_empty() {
+                return Err("sockets need an address to listen on".into());
+            }
+            // LISTEN_FDNAMES is separated by colons
+            if socket.name.as_ref().is_some_and(|name| name.contains(':')) {
+                return Err(format!(
+                    "{}: socket names cannot contain ':'",
+                    socket.listen
+                )
+                .into());
+            }
+        }
         for v in [&self.stdout, &self.stdin, &self.stderr] {
             if let IOHandler::FILE(filename) = v {
                 if fs::metadata(filename).is_err() {
@@ -53,6 +140,35 @@ impl ProgramConfig {
         }
         Ok(())
     }
+
+    /// Whether the process is started once it says or shows it is ready,
+    /// rather than after `succesful_start_after`.
+    pub fn waits_for_ready(&self) -> bool {
+        self.ready == ReadyOption::Notify || self.ready_when.is_some()
+    }
+
+    /// Seconds a process waited on to be ready has to get so, 0 to wait for
+    /// as long as it takes. One found ready by the daemon is not waited on
+    /// forever by default.
+    pub fn ready_timeout(&self) -> u32 {
+        match (self.start_timeout, &self.ready_when) {
+            (Some(timeout), _) => timeout,
+            (None, Some(_)) => DEFAULT_START_TIMEOUT,
+            (None, None) => 0,
+        }
+    }
+
+    /// Steps taken to stop a process before it is killed, `graceful_exit`
+    /// and `ttk` unless `stop_signals` are given.
+    pub fn stop_ladder(&self) -> Vec<StopSignal> {
+        match self.stop_signals.is_empty() {
+            true => vec![StopSignal {
+                signal: self.graceful_exit,
+                wait:   self.ttk,
+            }],
+            false => self.stop_signals.clone(),
+        }
+    }
 }
 
 impl Default for ProgramConfig {
@@ -75,6 +191,26 @@ impl Default for ProgramConfig {
             stdin:                 IOHandler::KNOWN(KnownHandler::DEFAULT),
             stdout:                IOHandler::KNOWN(KnownHandler::DEFAULT),
             stderr:                IOHandler::KNOWN(KnownHandler::DEFAULT),
+            allow_stdin:           false,
+            tty:                   false,
+            description:           String::new(),
+            group:                 None,
+            event_listener:        false,
+            event_filter:          vec![],
+            pre_start:             None,
+            post_start:            None,
+            pre_stop:              None,
+            post_stop:             None,
+            hook_timeout:          30,
+            stop_command:          None,
+            stop_signals:          vec![],
+            watch:                 vec![],
+            watch_rolling:         false,
+            ready:                 ReadyOption::Started,
+            watchdog:              0,
+            ready_when:            None,
+            start_timeout:         None,
+            sockets:               vec![],
         }
     }
 }
@@ -101,6 +237,26 @@ impl Clone for ProgramConfig {
             stdin:                 self.stdin.clone(),
             stdout:                self.stdout.clone(),
             stderr:                self.stderr.clone(),
+            allow_stdin:           self.allow_stdin,
+            tty:                   self.tty,
+            description:           self.description.clone(),
+            group:                 self.group.clone(),
+            event_listener:        self.event_listener,
+            event_filter:          self.event_filter.clone(),
+            pre_start:             self.pre_start.clone(),
+            post_start:            self.post_start.clone(),
+            pre_stop:              self.pre_stop.clone(),
+            post_stop:             self.post_stop.clone(),
+            hook_timeout:          self.hook_timeout,
+            stop_command:          self.stop_command.clone(),
+            stop_signals:          self.stop_signals.clone(),
+            watch:                 self.watch.clone(),
+            watch_rolling:         self.watch_rolling,
+            ready:                 self.ready,
+            watchdog:              self.watchdog,
+            ready_when:            self.ready_when.clone(),
+            start_timeout:         self.start_timeout,
+            sockets:               self.sockets.clone(),
         }
     }
 }
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// How a process tells it started.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadyOption {
+    /// Once it ran for `succesful_start_after` seconds
+    #[default]
+    Started,
+    /// Once it sent READY=1 to its NOTIFY_SOCKET, as with systemd's
+    /// Type=notify
+    Notify,
+}
+
+/// What the daemon looks for to know a process is ready, while it starts.
+/// Written as a map of one of the fields of `ReadyWhenFields`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(try_from = "ReadyWhenFields", into = "ReadyWhenFields")]
+pub enum ReadyWhen {
+    /// A line of its stdout matches this regex
+    StdoutMatches(String),
+    /// Something accepts connections on this port of localhost, only the
+    /// loopback address is probed
+    PortOpen(u16),
+    /// This file exists, relative to its workdir
+    FileExists(String),
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ReadyWhenFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stdout_matches: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port_open:      Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_exists:    Option<String>,
+}
+
+impl TryFrom<ReadyWhenFields> for ReadyWhen {
+    type Error = String;
+
+    fn try_from(fields: ReadyWhenFields) -> Result<Self, Self::Error> {
+        match (fields.stdout_matches, fields.port_open, fields.file_exists) {
+            (Some(pattern), None, None) => Ok(ReadyWhen::StdoutMatches(pattern)),
+            (None, Some(port), None) => Ok(ReadyWhen::PortOpen(port)),
+            (None, None, Some(path)) => Ok(ReadyWhen::FileExists(path)),
+            _ => Err("ready_when takes one of stdout_matches, port_open or \
+                      file_exists"
+                .into()),
+        }
+    }
+}
+
+impl From<ReadyWhen> for ReadyWhenFields {
+    fn from(ready_when: ReadyWhen) -> Self {
+        match ready_when {
+            ReadyWhen::StdoutMatches(pattern) => ReadyWhenFields {
+                stdout_matches: Some(pattern),
+                ..Default::default()
+            },
+            ReadyWhen::PortOpen(port) => ReadyWhenFields {
+                port_open: Some(port),
+                ..Default::default()
+            },
+            ReadyWhen::FileExists(path) => ReadyWhenFields {
+                file_exists: Some(path),
+                ..Default::default()
+            },
+        }
+    }
+}
@@ -1,9 +1,16 @@
+use std::fmt;
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+/// A Linux signal. Named in configs and commands with or without their SIG
+/// prefix, in any case, by number, or as SIGRTMIN+n and SIGRTMAX-n for the
+/// realtime ones.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
 pub enum Signal {
     /// Hangup
-    SIGHUP = 1,
+    SIGHUP,
     /// Interrupt
     SIGINT,
     /// Quit
@@ -54,38 +61,219 @@ pub enum Signal {
     SIGXFSZ,
     /// Virtual time alarm
     SIGVTALRM,
+    /// Profiling time alarm
+    SIGPROF,
+    /// Window size change
+    SIGWINCH,
+    /// I/O now possible
+    SIGIO,
+    /// Power failure restart
+    SIGPWR,
+    /// Bad system call
+    SIGSYS,
+    /// Realtime signal, by number
+    Realtime(i32),
+}
+
+/// The signals with a name, signal n at n - 1.
+const NAMED: [(Signal, &str); 31] = [
+    (Signal::SIGHUP, "SIGHUP"),
+    (Signal::SIGINT, "SIGINT"),
+    (Signal::SIGQUIT, "SIGQUIT"),
+    (Signal::SIGILL, "SIGILL"),
+    (Signal::SIGTRAP, "SIGTRAP"),
+    (Signal::SIGABRT, "SIGABRT"),
+    (Signal::SIGBUS, "SIGBUS"),
+    (Signal::SIGFPE, "SIGFPE"),
+    (Signal::SIGKILL, "SIGKILL"),
+    (Signal::SIGUSR1, "SIGUSR1"),
+    (Signal::SIGSEGV, "SIGSEGV"),
+    (Signal::SIGUSR2, "SIGUSR2"),
+    (Signal::SIGPIPE, "SIGPIPE"),
+    (Signal::SIGALRM, "SIGALRM"),
+    (Signal::SIGTERM, "SIGTERM"),
+    (Signal::SIGSTKFLT, "SIGSTKFLT"),
+    (Signal::SIGCHLD, "SIGCHLD"),
+    (Signal::SIGCONT, "SIGCONT"),
+    (Signal::SIGSTOP, "SIGSTOP"),
+    (Signal::SIGTSTP, "SIGTSTP"),
+    (Signal::SIGTTIN, "SIGTTIN"),
+    (Signal::SIGTTOU, "SIGTTOU"),
+    (Signal::SIGURG, "SIGURG"),
+    (Signal::SIGXCPU, "SIGXCPU"),
+    (Signal::SIGXFSZ, "SIGXFSZ"),
+    (Signal::SIGVTALRM, "SIGVTALRM"),
+    (Signal::SIGPROF, "SIGPROF"),
+    (Signal::SIGWINCH, "SIGWINCH"),
+    (Signal::SIGIO, "SIGIO"),
+    (Signal::SIGPWR, "SIGPWR"),
+    (Signal::SIGSYS, "SIGSYS"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignalError {
+    UnknownName(String),
+    UnknownNumber(i32),
+}
+
+impl fmt::Display for SignalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignalError::UnknownName(name) => write!(f, "unknown signal: {name}"),
+            SignalError::UnknownNumber(number) => write!(f, "unknown signal: {number}"),
+        }
+    }
 }
 
-impl From<i32> for Signal {
-    fn from(signal: i32) -> Self {
+impl std::error::Error for SignalError {}
+
+/// A step of the way a process is stopped: send `signal`, then wait `wait`
+/// seconds for the process to exit before going on to the next step.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub struct StopSignal {
+    pub signal: Signal,
+    pub wait:   u32, // seconds
+}
+
+impl FromStr for Signal {
+    type Err = SignalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || SignalError::UnknownName(s.to_string());
+        let upper = s.trim().to_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        if let Ok(number) = name.parse::<i32>() {
+            return Signal::try_from(number);
+        }
+        let offset = |offset: &str, sign: char| match offset {
+            "" => Some(0),
+            _ => offset.strip_prefix(sign)?.parse::<i32>().ok(),
+        };
+        let realtime = match (name.strip_prefix("RTMIN"), name.strip_prefix("RTMAX")) {
+            (Some(n), _) => {
+                offset(n, '+').and_then(|n| libc::SIGRTMIN().checked_add(n))
+            }
+            (_, Some(n)) => {
+                offset(n, '-').and_then(|n| libc::SIGRTMAX().checked_sub(n))
+            }
+            _ => None,
+        };
+        if let Some(number) = realtime {
+            return match (libc::SIGRTMIN()..=libc::SIGRTMAX()).contains(&number) {
+                true => Ok(Signal::Realtime(number)),
+                false => Err(unknown()),
+            };
+        }
+        NAMED
+            .iter()
+            .find(|(_, known)| known[3..] == *name)
+            .map(|(signal, _)| *signal)
+            .ok_or_else(unknown)
+    }
+}
+
+impl TryFrom<i32> for Signal {
+    type Error = SignalError;
+
+    fn try_from(number: i32) -> Result<Self, Self::Error> {
+        match number {
+            1..=31 => Ok(NAMED[number as usize - 1].0),
+            // glibc keeps the first realtime signals for itself, they are
+            // still ones a process can receive
+            32.. if number <= libc::SIGRTMAX() => Ok(Signal::Realtime(number)),
+            _ => Err(SignalError::UnknownNumber(number)),
+        }
+    }
+}
+
+impl TryFrom<String> for Signal {
+    type Error = SignalError;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> { name.parse() }
+}
+
+impl From<Signal> for i32 {
+    fn from(signal: Signal) -> i32 {
         match signal {
-            1 => Signal::SIGHUP,
-            2 => Signal::SIGINT,
-            3 => Signal::SIGQUIT,
-            4 => Signal::SIGILL,
-            5 => Signal::SIGTRAP,
-            6 => Signal::SIGABRT,
-            7 => Signal::SIGBUS,
-            8 => Signal::SIGFPE,
-            9 => Signal::SIGKILL,
-            10 => Signal::SIGUSR1,
-            11 => Signal::SIGSEGV,
-            12 => Signal::SIGUSR2,
-            13 => Signal::SIGPIPE,
-            14 => Signal::SIGALRM,
-            15 => Signal::SIGTERM,
-            16 => Signal::SIGSTKFLT,
-            17 => Signal::SIGCHLD,
-            18 => Signal::SIGCONT,
-            19 => Signal::SIGSTOP,
-            20 => Signal::SIGTSTP,
-            21 => Signal::SIGTTIN,
-            22 => Signal::SIGTTOU,
-            23 => Signal::SIGURG,
-            24 => Signal::SIGXCPU,
-            25 => Signal::SIGXFSZ,
-            26 => Signal::SIGVTALRM,
-            _ => panic!("Unknown signal"),
+            Signal::Realtime(number) => number,
+            named => NAMED
+                .iter()
+                .position(|(signal, _)| *signal == named)
+                .map_or(0, |i| i as i32 + 1),
+        }
+    }
+}
+
+impl From<Signal> for String {
+    fn from(signal: Signal) -> String { signal.to_string() }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Signal::Realtime(number) if number < libc::SIGRTMIN() => {
+                write!(f, "SIG{number}")
+            }
+            Signal::Realtime(number) if number == libc::SIGRTMIN() => {
+                write!(f, "SIGRTMIN")
+            }
+            Signal::Realtime(number) => {
+                write!(f, "SIGRTMIN+{}", number - libc::SIGRTMIN())
+            }
+            named => match NAMED.iter().find(|(signal, _)| *signal == named) {
+                Some((_, name)) => write!(f, "{name}"),
+                None => write!(f, "SIG?"),
+            },
+        }
+    }
+}
+
+// names read better than variants in statuses and logs
+impl fmt::Debug for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(self, f) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals_are_parsed_by_name_or_number() {
+        assert_eq!("SIGTERM".parse(), Ok(Signal::SIGTERM));
+        assert_eq!("hup".parse(), Ok(Signal::SIGHUP));
+        assert_eq!("28".parse(), Ok(Signal::SIGWINCH));
+        assert_eq!(
+            "SIGRTMIN+2".parse::<Signal>().map(i32::from),
+            Ok(libc::SIGRTMIN() + 2)
+        );
+        assert_eq!(
+            "RTMAX-1".parse::<Signal>().map(i32::from),
+            Ok(libc::SIGRTMAX() - 1)
+        );
+        assert!("SIGRTMAX+1".parse::<Signal>().is_err());
+        assert!("SIGNOPE".parse::<Signal>().is_err());
+    }
+
+    #[test]
+    fn every_signal_number_converts_back_and_forth() {
+        for number in 1..=libc::SIGRTMAX() {
+            let signal = Signal::try_from(number).unwrap();
+            assert_eq!(i32::from(signal), number);
+            assert_eq!(signal.to_string().parse(), Ok(signal));
         }
+        assert_eq!(Signal::try_from(0), Err(SignalError::UnknownNumber(0)));
+        assert!(Signal::try_from(libc::SIGRTMAX() + 1).is_err());
+    }
+
+    #[test]
+    fn signals_are_read_from_yaml_with_or_without_prefix() {
+        let steps: Vec<StopSignal> = serde_yaml::from_str(
+            "[{signal: TERM, wait: 20}, {signal: SIGRTMIN, wait: 1}]",
+        )
+        .unwrap();
+
+        assert_eq!(steps[0].signal, Signal::SIGTERM);
+        assert_eq!(steps[1].signal, Signal::Realtime(libc::SIGRTMIN()));
+        assert!(serde_yaml::from_str::<Signal>("SIGFOO").is_err());
     }
 }
@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+/// A socket the daemon listens on for a program, passed to its instances as
+/// systemd does for socket activation.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct SocketConfig {
+    /// `host:port` for TCP, a path for a Unix socket
+    pub listen: String,
+    /// Its name in LISTEN_FDNAMES, the program's name if None
+    #[serde(default)]
+    pub name:   Option<String>,
+}
+
+impl SocketConfig {
+    pub fn is_unix(&self) -> bool { self.listen.contains('/') }
+}
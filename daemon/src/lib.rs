@@ -4,6 +4,7 @@ pub mod backend;
 pub mod config;
 pub mod signal_handling;
 pub mod taskmaster;
+pub mod watcher;
 
 pub use backend::BackEnd;
 pub use config::TaskMasterConfig;
@@ -1,126 +1,104 @@
-use std::mem::MaybeUninit;
+//! Signals the daemon handles are blocked and read from a signalfd polled with
+//! the clients, so they are handled from the main loop like any other event
+//! rather than interrupting it wherever it is.
+
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::ptr::null_mut;
 
-use libc::{c_int, sigaction, sigemptyset, SA_SIGINFO};
+use common::syscall;
+use logger::warning;
 
 use super::config::Signal;
 
-static mut SIGHUP_CLOSURE: Option<Box<dyn FnMut()>> = None;
-static mut SIGCHLD_CLOSURE: Option<Box<dyn FnMut()>> = None;
+pub struct SignalFd {
+    fd: OwnedFd,
+}
 
-extern "C" fn signal_handler(sig: c_int) {
-    unsafe {
-        let mut closure = match Signal::from(sig) {
-            Signal::SIGHUP => SIGHUP_CLOSURE.as_mut(),
-            Signal::SIGCHLD => SIGCHLD_CLOSURE.as_mut(),
-            _ => panic!("unknown signal received"),
-        };
-        if let Some(ref mut handler) = closure {
-            handler();
+impl SignalFd {
+    /// Blocks `signals` for them to be read from the descriptor instead. Must
+    /// be made before any thread is started, a thread that does not block
+    /// them would still take them.
+    pub fn new(signals: &[Signal]) -> io::Result<SignalFd> {
+        let mask = signal_set(signals);
+        syscall!(pthread_sigmask(libc::SIG_BLOCK, &mask, null_mut()))?;
+        let flags = libc::SFD_NONBLOCK | libc::SFD_CLOEXEC;
+        let fd = syscall!(signalfd(-1, &mask, flags))?;
+        Ok(SignalFd {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// The signals received since last time, each given once however many
+    /// times it was sent.
+    pub fn read(&self) -> io::Result<Vec<Signal>> {
+        let mut signals = vec![];
+        let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::<libc::signalfd_siginfo>();
+        loop {
+            let ptr = &mut info as *mut libc::signalfd_siginfo as *mut libc::c_void;
+            match syscall!(read(self.fd.as_raw_fd(), ptr, size)) {
+                Ok(bytes) if bytes as usize == size => {
+                    match Signal::try_from(info.ssi_signo as i32) {
+                        Ok(signal) if !signals.contains(&signal) => {
+                            signals.push(signal)
+                        }
+                        Ok(_) => {}
+                        Err(e) => warning!("{e}"),
+                    }
+                }
+                Ok(_) => return Ok(signals),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(signals),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
         }
     }
 }
 
-pub fn install_signal_handler(signal: Signal, handler: impl FnMut() + 'static) {
-    let mut action: sigaction = unsafe { MaybeUninit::zeroed().assume_init() };
-    unsafe {
-        match signal {
-            Signal::SIGHUP => SIGHUP_CLOSURE = Some(Box::new(handler)),
-            Signal::SIGCHLD => SIGCHLD_CLOSURE = Some(Box::new(handler)),
-            _ => panic!(
-                "Invalid signal to handle. If you want to handle a new signal, add it \
-                 to the match statement in signal_handler.rs"
-            ),
-        };
-    }
-    action.sa_sigaction = signal_handler as usize;
-    action.sa_flags = SA_SIGINFO;
-    unsafe { sigemptyset(&mut action.sa_mask) };
-    unsafe { sigaction(signal as i32, &action, null_mut::<sigaction>()) };
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd { self.fd.as_raw_fd() }
 }
 
-pub fn install_sighup_handler(handler: impl FnMut() + 'static) {
-    install_signal_handler(Signal::SIGHUP, handler);
+/// Unblocks every signal. The mask is inherited through exec, children
+/// would never see the signals the daemon reads from its SignalFd.
+pub fn unblock_signals() -> io::Result<()> {
+    let mask = signal_set(&[]);
+    syscall!(sigprocmask(libc::SIG_SETMASK, &mask, null_mut()))?;
+    Ok(())
 }
 
-pub fn install_sigchld_handler(handler: impl FnMut() + 'static) {
-    install_signal_handler(Signal::SIGCHLD, handler);
+fn signal_set(signals: &[Signal]) -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for &signal in signals {
+            libc::sigaddset(&mut set, signal.into());
+        }
+        set
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::taskmaster::{Status, TaskMaster};
-
-    fn initialize() {
-        unsafe {
-            SIGHUP_CLOSURE = None;
-            SIGCHLD_CLOSURE = None;
-        };
-    }
 
     #[test]
-    fn sighup_handler_test() {
-        initialize();
-        let mut taskmaster = TaskMaster::new();
-        let ptr: *mut Status = &mut taskmaster.status;
-        unsafe {
-            install_sighup_handler(move || {
-                *ptr = Status::Reloading;
-            });
-
-            assert_eq!(taskmaster.status, Status::Starting);
-            signal_handler(libc::SIGHUP);
-            assert_eq!(taskmaster.status, Status::Reloading);
-            taskmaster.status = Status::Active;
-            signal_handler(libc::SIGHUP);
-            assert_eq!(taskmaster.status, Status::Reloading);
-            taskmaster.status = Status::Active;
-            signal_handler(libc::SIGHUP);
-            assert_eq!(taskmaster.status, Status::Reloading);
-            taskmaster.status = Status::Active;
-        }
-    }
+    fn signals_are_read_once_each() {
+        let signals = SignalFd::new(&[Signal::SIGUSR1, Signal::SIGUSR2]).unwrap();
+        assert_eq!(signals.read().unwrap(), []);
 
-    #[test]
-    fn sigchld_handler_test() {
-        initialize();
-        let mut alive = true;
-        let ptr: *mut bool = &mut alive;
         unsafe {
-            install_sigchld_handler(move || *ptr = !*ptr);
-
-            assert!(alive);
-            signal_handler(libc::SIGCHLD);
-            assert!(!alive);
-            signal_handler(libc::SIGCHLD);
-            assert!(alive);
+            libc::raise(libc::SIGUSR2);
+            libc::raise(libc::SIGUSR1);
+            libc::raise(libc::SIGUSR2);
         }
-    }
-
-    #[test]
-    fn unmangled_executions_test() {
-        initialize();
-        let mut chld_business = String::from("chld_business");
-        let mut hup_business = String::from("hup_business");
-        let chld_ptr: *mut String = &mut chld_business;
-        let hup_ptr: *mut String = &mut hup_business;
-        unsafe {
-            install_signal_handler(Signal::SIGHUP, move || {
-                *hup_ptr = "hup received".to_string();
-            });
-            install_signal_handler(Signal::SIGCHLD, move || {
-                *chld_ptr = "child received".to_string();
-            });
 
-            assert_eq!(chld_business, "chld_business");
-            assert_eq!(hup_business, "hup_business");
-            signal_handler(libc::SIGHUP);
-            assert_eq!(hup_business, "hup received");
-            assert_eq!(chld_business, "chld_business");
-            signal_handler(libc::SIGCHLD);
-            assert_eq!(hup_business, "hup received");
-            assert_eq!(chld_business, "child received");
-        }
+        let mut received = signals.read().unwrap();
+        received.sort_by_key(|&signal| i32::from(signal));
+        assert_eq!(received, [Signal::SIGUSR1, Signal::SIGUSR2]);
+        assert_eq!(signals.read().unwrap(), []);
+        unblock_signals().unwrap();
     }
 }
@@ -1,10 +1,8 @@
 mod class;
 mod client;
-mod request_factory;
 mod status;
 
 pub use class::TaskMaster;
 pub use client::Client;
 pub use common::ClientState;
-pub use request_factory::RequestFactory;
 pub use status::Status;
@@ -1,22 +1,53 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io;
+use std::os::fd::AsRawFd;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use common::server::{Key, Server, ServerError, SERVER_KEY};
-use common::{ClientState, Cmd, Request, DAEMON_SOCKET_PATH};
-use logger::{debug, error, info};
+use common::server::{Key, Server, ServerError, MAX_FRAME_LENGTH, SERVER_KEY};
+use common::{
+    ClientState,
+    Cmd,
+    Encoding,
+    Request,
+    RequestError,
+    Response,
+    DAEMON_SOCKET_PATH,
+};
+use logger::{debug, error, info, warning};
 
-use super::{Client, RequestFactory, Status};
-use crate::signal_handling::{install_sigchld_handler, install_sighup_handler};
+use super::{Client, Status};
+use crate::backend::Target;
+use crate::config::Signal;
+use crate::signal_handling::SignalFd;
 use crate::{BackEnd, TaskMasterConfig};
 
+/// Key of the signalfd in the epoll set, below those of the clients.
+const SIGNAL_KEY: Key = 1;
+/// Key of the config watcher in the epoll set.
+const WATCH_KEY: Key = 2;
+/// Key of the watcher of the programs' `watch` paths in the epoll set.
+const PROGRAMS_WATCH_KEY: Key = 3;
+
+/// How long the config file must stay unchanged before it is reloaded, it
+/// is often written several times in a row.
+const CONFIG_DEBOUNCE: Duration = Duration::from_millis(500);
+/// How long the clients are given to take what is left for them once the
+/// programs stopped, the daemon exits anyway after it.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct TaskMaster {
     pub server:      Server,
     pub backend:     BackEnd,
     pub status:      Status,
-    pub factory:     RequestFactory,
     pub clients:     HashMap<Key, Client>,
+    signals:         Option<SignalFd>,
+    /// When the config file changed last, until it is reloaded
+    config_changed:  Option<Instant>,
     config_filename: String,
+    /// When the last program stopped while exiting
+    stopped_at:      Option<Instant>,
 }
 
 impl TaskMaster {
@@ -26,40 +57,46 @@ impl TaskMaster {
             server:          Server::new(DAEMON_SOCKET_PATH),
             backend:         BackEnd::default(),
             status:          Status::default(),
-            factory:         RequestFactory::default(),
             config_filename: String::default(),
             clients:         HashMap::default(),
+            signals:         None,
+            config_changed:  None,
+            stopped_at:      None,
         }
     }
 
     pub fn build(&mut self, config_filename: &str) -> Result<(), ServerError> {
         self.server.build()?;
+        // before any child is spawned, they must not exit unnoticed
+        let signals = SignalFd::new(&[
+            Signal::SIGCHLD,
+            Signal::SIGHUP,
+            Signal::SIGTERM,
+            Signal::SIGINT,
+        ])?;
+        self.server.add_source(SIGNAL_KEY, signals.as_raw_fd())?;
+        self.signals = Some(signals);
+        self.config_filename = config_filename.into();
         let file = File::open(config_filename)?;
         let config = TaskMasterConfig::from(file);
         config.validate()?;
         self.backend = BackEnd::new(config);
+        self.backend.config_file = config_filename.into();
         self.backend.start();
-
-        let ptr: *mut Status = &mut self.status;
-        install_sighup_handler(move || unsafe {
-            *ptr = Status::Reloading;
-        });
-
-        let backend_ptr: *mut BackEnd = &mut self.backend;
-        install_sigchld_handler(move || unsafe {
-            (*backend_ptr).handle_dead_processes();
-        });
+        self.register_watchers();
         self.status = Status::Active;
         Ok(())
     }
 
     pub fn reload(&mut self) -> io::Result<()> {
-        let file = File::open(&self.config_filename)?;
         if let Status::Reloading = self.status {
             debug!("Reloading!!!");
-            self.backend
-                .update(file.into())
-                .expect("Failed to reload config");
+            match self.backend.read_config() {
+                Ok(config) => {
+                    self.backend.reload_config(config, false);
+                }
+                Err(e) => error!("config not reloaded: {e}"),
+            }
             self.status = Status::Active;
         };
         Ok(())
@@ -67,6 +104,7 @@ impl TaskMaster {
 
     pub fn serve_routine(&mut self) -> Result<(), ServerError> {
         // info!("#{} AWAITING", self.server.key);
+        self.register_watchers();
         self.server.epoll_wait()?;
         // self.backend.dump_processes_status();
         for ev in self.server.get_events() {
@@ -77,9 +115,22 @@ impl TaskMaster {
                 }
                 continue;
             }
+            if key == SIGNAL_KEY {
+                self.handle_signals();
+                continue;
+            }
+            if key == WATCH_KEY {
+                self.handle_config_changes();
+                continue;
+            }
+            if key == PROGRAMS_WATCH_KEY {
+                self.backend.handle_watched_changes();
+                continue;
+            }
             if (ev.events & libc::EPOLLIN as u32) != 0 {
                 if self.receive(key).is_err() {
                     self.server.clients.remove(&key);
+                    self.clients.remove(&key);
                     continue;
                 }
             } else if (ev.events & libc::EPOLLOUT as u32) != 0 {
@@ -89,17 +140,159 @@ impl TaskMaster {
                 error!("Unexpected event: {}", ev);
             }
         }
+        let settled = self
+            .config_changed
+            .as_ref()
+            .is_some_and(|changed| changed.elapsed() >= CONFIG_DEBOUNCE);
+        if settled && self.status == Status::Active {
+            info!("{} changed, reloading", self.config_filename);
+            self.config_changed = None;
+            self.status = Status::Reloading;
+        }
         Ok(())
     }
 
+    /// Adds the watchers a reload created to the epoll set, those they
+    /// replaced left it when closed.
+    fn register_watchers(&mut self) {
+        if !std::mem::take(&mut self.backend.watchers_changed) {
+            return;
+        }
+        if let Some(watcher) = &self.backend.config_watcher {
+            if let Err(e) = self.server.add_source(WATCH_KEY, watcher.as_raw_fd()) {
+                error!("{} not watched: {e}", self.config_filename);
+            }
+        }
+        if let Some(watcher) = &self.backend.watcher {
+            if let Err(e) = self
+                .server
+                .add_source(PROGRAMS_WATCH_KEY, watcher.as_raw_fd())
+            {
+                error!("programs not watched: {e}");
+            }
+        }
+    }
+
+    fn handle_config_changes(&mut self) {
+        let Some(watcher) = &self.backend.config_watcher else {
+            return;
+        };
+        match watcher.read() {
+            Ok(changed) if !changed.is_empty() => {
+                debug!("{} changed", self.config_filename);
+                self.config_changed = Some(Instant::now());
+            }
+            Ok(_) => {}
+            Err(e) => error!("{}: {e}", self.config_filename),
+        }
+    }
+
+    fn handle_signals(&mut self) {
+        let Some(signals) = &self.signals else {
+            return;
+        };
+        let signals = match signals.read() {
+            Ok(signals) => signals,
+            Err(e) => return error!("signals: {e}"),
+        };
+        for signal in signals {
+            debug!("{signal} received");
+            match signal {
+                Signal::SIGCHLD => self.backend.handle_dead_processes(),
+                Signal::SIGHUP => self.status = Status::Reloading,
+                // told again while exiting, the programs are not waited on
+                Signal::SIGTERM | Signal::SIGINT if self.status == Status::Exiting => {
+                    self.backend.kill_programs()
+                }
+                Signal::SIGTERM | Signal::SIGINT => self.shutdown(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Stops every program before exiting, streams are ended for clients to
+    /// know, once they sent what they had left, the shutdown event included.
+    /// The daemon keeps answering until the last program stopped.
+    pub fn shutdown(&mut self) {
+        if self.status == Status::Exiting {
+            return;
+        }
+        self.status = Status::Exiting;
+        self.backend.shutdown();
+        self.backend.collect_events();
+        for (key, client) in self.clients.iter_mut() {
+            let streams = client.cancel(None);
+            if streams.is_empty() {
+                continue;
+            }
+            for mut stream in streams {
+                if self.backend.has_pending_output(&stream) {
+                    let response = self.backend.handle_request(&mut stream);
+                    client.responses.push_back(response);
+                }
+                stream.finished = true;
+                let response =
+                    Response::new(&stream, Err("daemon is shutting down".into()));
+                client.responses.push_back(response);
+            }
+            let event = match client.state {
+                ClientState::Unattached => Server::write_event(*key),
+                ClientState::Attached(_) => Server::read_write_event(*key),
+            };
+            if let Err(e) = self.server.modify_interest(event) {
+                error!("#{key} {e}");
+            }
+        }
+    }
+
+    /// Whether the daemon is done shutting down: its programs stopped and
+    /// the clients were sent what was left for them, or took too long to
+    /// take it.
+    pub fn can_exit(&mut self) -> bool {
+        if self.status != Status::Exiting || !self.backend.stop_programs() {
+            return false;
+        }
+        let stopped_at = *self.stopped_at.get_or_insert_with(|| {
+            info!("All programs stopped");
+            Instant::now()
+        });
+        let drained = self.server.clients.iter().all(|(key, connection)| {
+            !connection.has_pending_output() && !self.has_ready_responses(*key)
+        });
+        if !drained && stopped_at.elapsed() >= DRAIN_TIMEOUT {
+            warning!("clients still waiting on responses, exiting anyway");
+        }
+        drained || stopped_at.elapsed() >= DRAIN_TIMEOUT
+    }
+
+    /// How the daemon exits once shut down, failing if processes had to be
+    /// killed.
+    pub fn exit_code(&self) -> ExitCode {
+        match self.backend.killed_on_stop() {
+            0 => ExitCode::SUCCESS,
+            _ => ExitCode::FAILURE,
+        }
+    }
+
     fn receive(&mut self, key: Key) -> Result<(), ServerError> {
-        let mut msg = self.server.recv(key)?;
-        self.factory.insert(key, &mut msg);
-        if let Some(request) = self.factory.parse(key) {
-            self.insert_request(key, request);
-            self.server.modify_interest(Server::write_event(key))?;
-        } else {
-            self.request_read(key)?;
+        let messages = self.server.recv(key)?;
+        let received = !messages.is_empty();
+        for message in messages {
+            let parsed = match std::str::from_utf8(&message) {
+                Ok(raw) => Request::parse(raw),
+                Err(_) => Err("request is not UTF-8".into()),
+            };
+            match parsed {
+                Ok(mut request) => {
+                    request.client_key = key;
+                    self.insert_request(key, request);
+                }
+                Err(e) => self.reject(key, &String::from_utf8_lossy(&message), e),
+            }
+        }
+        match received {
+            true => self.server.modify_interest(Server::write_event(key))?,
+            false => self.request_read(key)?,
         }
         Ok(())
     }
@@ -113,58 +306,168 @@ impl TaskMaster {
     }
 
     fn respond(&mut self, key: Key) -> Result<(), ServerError> {
+        // what the client did not take last time goes first
+        if !self.server.flush(key)? {
+            return self.server.modify_interest(Server::write_event(key));
+        }
+        let backend = &self.backend;
         let client = self.clients.get_mut(&key).unwrap();
-        let request = client.requests.pop_front();
-        let response = match request {
+        let response = match client.take_ready(|r| backend.has_pending_output(r)) {
+            Some(request) if request.command == Cmd::Cancel => {
+                Some(self.cancel(key, request))
+            }
+            Some(mut request) if request.command == Cmd::Shutdown => {
+                request.finished = true;
+                self.shutdown();
+                Some(Response::new(&request, Ok("shutting down".into())))
+            }
             Some(mut request) => {
                 let response = self.backend.handle_request(&mut request);
                 let client = self.clients.get_mut(&key).unwrap();
                 if client.state != request.state {
                     client.state = request.state.clone();
                 }
+                // streams take turns with the requests queued after them
                 if !response.finished {
-                    client.requests.insert(0, request);
+                    client.requests.push_back(request);
                 }
                 Some(response)
             }
             None => client.responses.pop_front(),
         };
         if response.is_none() {
-            return Ok(());
+            return self.request_read(key);
         } else {
             debug!("auto response mock fetched");
         }
         let response = response.unwrap();
+        let mut message = response.encode();
+        if message.len() > MAX_FRAME_LENGTH {
+            warning!("#{key} response of {} bytes dropped", message.len());
+            message = Response {
+                message: format!("response exceeds {MAX_FRAME_LENGTH} bytes"),
+                failed: true,
+                ..response
+            }
+            .encode();
+        }
         // the connection is kept alive until dropped by frontend
-        self.server.send(key, &response.message)?;
-        self.request_read(key)?;
+        let sent = self.server.send(key, message.as_bytes())?;
+        match !sent || self.has_ready_responses(key) {
+            true => self.server.modify_interest(Server::write_event(key))?,
+            false => self.request_read(key)?,
+        }
         info!("#{key} SENT");
         Ok(())
     }
 
-    pub fn insert_request(&mut self, k: Key, mut request: Request) {
-        if let Some(client) = self.clients.get_mut(&k) {
-            request.state = client.state.clone();
-            if (request.state != ClientState::Unattached
-                && request.command != Cmd::Unattach)
-            {
-                request.command = Cmd::Other(request.command.into());
+    fn has_ready_responses(&self, k: Key) -> bool {
+        self.clients.get(&k).is_some_and(|client| {
+            !client.responses.is_empty()
+                || client.has_ready(|r| self.backend.has_pending_output(r))
+        })
+    }
+
+    /// Ends the request a CANCEL names, or every stream of the client when
+    /// it names none. Each cancelled request gets a last, failed response.
+    fn cancel(&mut self, k: Key, mut request: Request) -> Response {
+        request.finished = true;
+
+        let id = match request.arguments.first().map(|arg| arg.parse::<u64>()) {
+            None => None,
+            Some(Ok(id)) => Some(id),
+            Some(Err(_)) => {
+                return Response::new(&request, Err("CANCEL takes a request id".into()))
             }
-            client.requests.push_back(request);
-        } else {
-            let mut client = Client::new();
-            client.requests.push_back(request);
-            self.clients.insert(k, client);
+        };
+        let client = self.clients.get_mut(&k).unwrap();
+        let cancelled = client.cancel(id);
+        for mut stream in cancelled.iter().cloned() {
+            stream.finished = true;
+            let response = Response::new(&stream, Err("cancelled".into()));
+            client.responses.push_back(response);
         }
+        info!("#{k} {} requests cancelled", cancelled.len());
+        let result = match (id, cancelled.len()) {
+            (Some(id), 0) => Err(format!("no request {id} in flight").into()),
+            (Some(id), _) => Ok(format!("request {id} cancelled")),
+            (None, count) => Ok(format!("{count} streams cancelled")),
+        };
+        Response::new(&request, result)
+    }
+
+    /// Answers a line that is not a request with an error.
+    fn reject(&mut self, k: Key, raw: &str, error: RequestError) {
+        warning!("#{k} invalid request: {error}");
+        let client = self.clients.entry(k).or_default();
+        let request = Request {
+            finished: true,
+            client_key: k,
+            encoding: Encoding::of(raw),
+            state: client.state.clone(),
+            ..Default::default()
+        };
+        let response = Response::new(&request, Err(error.to_string().into()));
+        client.responses.push_back(response);
+    }
+
+    pub fn insert_request(&mut self, k: Key, mut request: Request) {
+        let client = self.clients.entry(k).or_default();
+        client.encoding = request.encoding;
+        request.state = client.state.clone();
+        if (request.state != ClientState::Unattached && !request.command.is_control()) {
+            request.command = Cmd::Other(request.command.into());
+        }
+        if request.id != 0 && client.requests.iter().any(|r| r.id == request.id) {
+            request.finished = true;
+            let error = format!("request {} is already in flight", request.id);
+            client
+                .responses
+                .push_back(Response::new(&request, Err(error.into())));
+            return;
+        }
+        client.requests.push_back(request);
     }
 
     pub fn generate_responses(&mut self) {
-        let responses = self.backend.handle_outputs();
+        let attached: Vec<(Key, Target)> = self
+            .clients
+            .iter()
+            .filter_map(|(key, client)| match &client.state {
+                ClientState::Attached(target) => Some((*key, target.parse().ok()?)),
+                ClientState::Unattached => None,
+            })
+            .collect();
+        self.backend.collect_events();
+        self.backend.dispatch_events();
+        let responses = self.backend.handle_outputs(&attached);
         for response in responses.into_iter() {
             if let Some(c) = self.clients.get_mut(&response.client_key) {
                 if c.state != ClientState::Unattached {
                     debug!("auto response mock generated");
-                    c.responses.push_back(response);
+                    let encoding = c.encoding;
+                    c.responses.push_back(Response {
+                        encoding,
+                        ..response
+                    });
+                }
+            }
+        }
+        self.resume_streams();
+    }
+
+    /// Wakes up the clients whose streaming request has something new to
+    /// send, the others stay idle until they have.
+    fn resume_streams(&mut self) {
+        for (key, client) in self.clients.iter() {
+            let mut streams = client.requests.iter().filter(|r| r.is_streaming());
+            if streams.any(|r| self.backend.has_pending_output(r)) {
+                let event = match client.state {
+                    ClientState::Unattached => Server::write_event(*key),
+                    ClientState::Attached(_) => Server::read_write_event(*key),
+                };
+                if let Err(e) = self.server.modify_interest(event) {
+                    error!("#{key} {e}");
                 }
             }
         }
@@ -176,7 +479,63 @@ impl TaskMaster {
     }
 
     pub fn is_client_attached(&self, k: Key) -> bool {
-        let c = self.clients.get(&k).expect("the client to exist");
-        c.state != ClientState::Unattached
+        self.clients
+            .get(&k)
+            .is_some_and(|c| c.state != ClientState::Unattached)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    fn taskmaster(socket: &str) -> TaskMaster {
+        let mut server = Server::new(socket);
+        server.build().unwrap();
+        TaskMaster {
+            server,
+            backend: BackEnd::default(),
+            status: Status::Active,
+            clients: HashMap::default(),
+            signals: None,
+            config_changed: None,
+            config_filename: String::default(),
+            stopped_at: None,
+        }
+    }
+
+    #[test]
+    fn subscribers_get_the_shutdown_event_before_the_daemon_exits() {
+        let socket = std::env::temp_dir().join("taskmaster_shutdown_test.sock");
+        let socket = socket.display().to_string();
+        let mut taskmaster = taskmaster(&socket);
+        let mut client = UnixStream::connect(&socket).unwrap();
+        client.write_all(b"SUBSCRIBE\n").unwrap();
+        while !taskmaster
+            .clients
+            .values()
+            .any(|client| client.requests.iter().any(Request::is_streaming))
+        {
+            taskmaster.generate_responses();
+            taskmaster.serve_routine().unwrap();
+        }
+
+        taskmaster.shutdown();
+        while !taskmaster.can_exit() {
+            taskmaster.generate_responses();
+            taskmaster.serve_routine().unwrap();
+        }
+        drop(taskmaster);
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        let event = output.find("shutdown\n").expect("shutdown event sent");
+        let notice = output
+            .find("daemon is shutting down")
+            .expect("shutdown notice sent");
+        assert!(event < notice);
     }
 }
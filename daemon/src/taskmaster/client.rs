@@ -1,14 +1,85 @@
 use std::collections::VecDeque;
 
-use common::{ClientState, Request, Response};
+use common::{ClientState, Encoding, Request, Response};
 
 #[derive(Debug, Default)]
 pub struct Client {
     pub state:     ClientState,
     pub requests:  VecDeque<Request>,
     pub responses: VecDeque<Response>,
+    /// Encoding of the last request, used for unsolicited responses
+    pub encoding:  Encoding,
 }
 
 impl Client {
     pub fn new() -> Client { Client::default() }
+
+    /// Takes the first request that can be answered now, a stream only once
+    /// it has something new to send.
+    pub fn take_ready(
+        &mut self,
+        has_output: impl Fn(&Request) -> bool,
+    ) -> Option<Request> {
+        let position = self
+            .requests
+            .iter()
+            .position(|r| !r.is_streaming() || has_output(r))?;
+        self.requests.remove(position)
+    }
+
+    pub fn has_ready(&self, has_output: impl Fn(&Request) -> bool) -> bool {
+        self.requests
+            .iter()
+            .any(|r| !r.is_streaming() || has_output(r))
+    }
+
+    /// Removes the request with the given id, or every stream without one.
+    pub fn cancel(&mut self, id: Option<u64>) -> VecDeque<Request> {
+        let (cancelled, kept) = self.requests.drain(..).partition(|r| match id {
+            Some(id) => r.id == id,
+            None => r.is_streaming(),
+        });
+        self.requests = kept;
+        cancelled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u64, streaming: bool) -> Request {
+        Request {
+            id,
+            cursor: streaming.then_some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn idle_streams_let_later_requests_through() {
+        let mut client = Client::new();
+        client
+            .requests
+            .extend([request(1, true), request(2, false)]);
+
+        assert_eq!(client.take_ready(|_| false).map(|r| r.id), Some(2));
+        assert!(!client.has_ready(|_| false));
+        assert_eq!(client.take_ready(|_| true).map(|r| r.id), Some(1));
+    }
+
+    #[test]
+    fn cancel_without_id_ends_streams_only() {
+        let mut client = Client::new();
+        client
+            .requests
+            .extend([request(1, true), request(2, false), request(3, true)]);
+
+        let cancelled = client.cancel(None);
+
+        assert_eq!(cancelled.iter().map(|r| r.id).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(client.requests.len(), 1);
+        assert_eq!(client.cancel(Some(2)).len(), 1);
+        assert!(client.cancel(Some(2)).is_empty());
+    }
 }
@@ -5,4 +5,6 @@ pub enum Status {
     Starting,
     Reloading,
     Active,
+    /// Stopping every program before exiting
+    Exiting,
 }
@@ -0,0 +1,167 @@
+//! Files and directories watched for changes with inotify, polled with the
+//! clients.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsString};
+use std::io::{self, ErrorKind};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::{fs, mem, ptr};
+
+use common::syscall;
+
+const MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVED_FROM
+    | libc::IN_CREATE
+    | libc::IN_DELETE;
+
+pub struct Watcher {
+    fd:    OwnedFd,
+    /// Watched paths by the watch descriptor of a directory, and the entry
+    /// they are in it, None for any entry
+    paths: HashMap<i32, Vec<(PathBuf, Option<OsString>)>>,
+}
+
+impl Watcher {
+    pub fn new() -> io::Result<Watcher> {
+        let fd = syscall!(inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC))?;
+        Ok(Watcher {
+            fd:    unsafe { OwnedFd::from_raw_fd(fd) },
+            paths: HashMap::new(),
+        })
+    }
+
+    /// Watches a directory for changes to its entries, or a file through its
+    /// directory, as editors and config management replace files rather than
+    /// write to them.
+    pub fn watch(&mut self, path: &Path) -> io::Result<()> {
+        match (path.is_dir(), path.parent(), path.file_name()) {
+            (true, _, _) => self.add_watch(path, path, None),
+            (false, Some(parent), Some(name)) if parent != Path::new("") => {
+                self.add_watch(parent, path, Some(name.into()))
+            }
+            (false, _, name) => {
+                self.add_watch(Path::new("."), path, name.map(Into::into))
+            }
+        }
+    }
+
+    /// Watches a path, and the directories below it but hidden ones, a
+    /// change anywhere being reported as a change to `path`. Directories
+    /// created later are not watched, only their creation is noticed.
+    pub fn watch_tree(&mut self, path: &Path) -> io::Result<()> {
+        self.watch(path)?;
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let hidden = entry.file_name().as_bytes().starts_with(b".");
+                if hidden || !entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    continue;
+                }
+                self.add_watch(&entry.path(), path, None)?;
+                dirs.push(entry.path());
+            }
+        }
+        Ok(())
+    }
+
+    fn add_watch(
+        &mut self,
+        dir: &Path,
+        path: &Path,
+        entry: Option<OsString>,
+    ) -> io::Result<()> {
+        let dir = CString::new(dir.as_os_str().as_bytes()).map_err(io::Error::other)?;
+        let wd = syscall!(inotify_add_watch(self.fd.as_raw_fd(), dir.as_ptr(), MASK))?;
+        self.paths
+            .entry(wd)
+            .or_default()
+            .push((path.to_path_buf(), entry));
+        Ok(())
+    }
+
+    /// The watched paths that changed since last time, each given once.
+    pub fn read(&self) -> io::Result<Vec<PathBuf>> {
+        let mut changed = vec![];
+        let mut buf = [0_u8; 4096];
+        loop {
+            let ptr = buf.as_mut_ptr() as *mut libc::c_void;
+            let bytes = match syscall!(read(self.fd.as_raw_fd(), ptr, buf.len())) {
+                Ok(bytes) => bytes as usize,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(changed),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let mut offset = 0;
+            while offset + mem::size_of::<libc::inotify_event>() <= bytes {
+                let event: libc::inotify_event = unsafe {
+                    ptr::read_unaligned(
+                        buf[offset..].as_ptr() as *const libc::inotify_event
+                    )
+                };
+                let start = offset + mem::size_of::<libc::inotify_event>();
+                let name = &buf[start..start + event.len as usize];
+                let name =
+                    &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                offset = start + event.len as usize;
+
+                for (path, entry) in self.paths.get(&event.wd).into_iter().flatten() {
+                    let matches =
+                        entry.as_ref().is_none_or(|entry| entry.as_bytes() == name);
+                    if matches && !changed.contains(path) {
+                        changed.push(path.clone());
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for Watcher {
+    fn as_raw_fd(&self) -> RawFd { self.fd.as_raw_fd() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaced_files_are_noticed() {
+        let dir = std::env::temp_dir().join("taskmaster_watcher");
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.yml");
+        fs::write(&config, "programs: {}").unwrap();
+        let mut watcher = Watcher::new().unwrap();
+        watcher.watch(&config).unwrap();
+
+        fs::write(dir.join("other.yml"), "").unwrap();
+        assert!(watcher.read().unwrap().is_empty());
+
+        fs::write(dir.join("config.yml.tmp"), "programs: {}").unwrap();
+        fs::rename(dir.join("config.yml.tmp"), &config).unwrap();
+        assert_eq!(watcher.read().unwrap(), [config]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn changes_below_a_tree_are_reported_for_its_root() {
+        let dir = std::env::temp_dir().join("taskmaster_watcher_tree");
+        fs::create_dir_all(dir.join("src/backend")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        let mut watcher = Watcher::new().unwrap();
+        watcher.watch_tree(&dir).unwrap();
+
+        fs::write(dir.join(".git/index"), "").unwrap();
+        assert!(watcher.read().unwrap().is_empty());
+
+        fs::write(dir.join("src/backend/main.rs"), "").unwrap();
+        fs::write(dir.join("src/lib.rs"), "").unwrap();
+        assert_eq!(watcher.read().unwrap(), std::slice::from_ref(&dir));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}